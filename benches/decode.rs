@@ -0,0 +1,99 @@
+// rawzeo::benches::decode
+//
+//! Benchmarks the decoding of a synthetic capture.
+//
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+
+use rawzeo::{frames, Decoder};
+
+/// The number of seconds of the synthetic capture.
+const SECONDS: u32 = 600;
+
+/// Returns an encoded frame.
+fn frame(time: u32, seqnum: u8, datatype: u8, data: &[u8]) -> Vec<u8> {
+    let len = data.len() as u16 + 1;
+    let checksum = data.iter().fold(datatype, |sum, b| sum.wrapping_add(*b));
+    let mut f = vec![b'A', b'4', checksum];
+    f.extend(len.to_le_bytes());
+    f.extend((!len).to_le_bytes());
+    f.push(time as u8);
+    f.extend(0_u16.to_le_bytes());
+    f.push(seqnum);
+    f.push(datatype);
+    f.extend(data);
+    f
+}
+
+/// Returns a synthetic capture with one slice of data per second.
+fn capture() -> Vec<u8> {
+    let waveform: Vec<u8> = (0..256).map(|i| i as u8).collect();
+    let mut bytes = vec![];
+    let mut seqnum = 0_u8;
+    let mut push = |bytes: &mut Vec<u8>, time: u32, datatype: u8, data: &[u8]| {
+        bytes.extend(frame(time, seqnum, datatype, data));
+        seqnum = seqnum.wrapping_add(1);
+    };
+    for s in 0..SECONDS {
+        let time = 1_672_619_531 + s;
+        push(&mut bytes, time, 0x8A, &time.to_le_bytes());
+        push(&mut bytes, time, 0x80, &waveform);
+        push(&mut bytes, time, 0x83, &waveform[..14]);
+        push(&mut bytes, time, 0x84, &[9, 0, 0, 0]);
+        push(&mut bytes, time, 0x97, &[0xA2, 0x81, 0x2A, 0x83]);
+        push(&mut bytes, time, 0x9C, &[0, 0, 0, 0]);
+        push(&mut bytes, time, 0x02, &s.to_le_bytes());
+    }
+    bytes
+}
+
+fn decode(c: &mut Criterion) {
+    let capture = capture();
+    let mut group = c.benchmark_group("decode");
+    group.throughput(Throughput::Bytes(capture.len() as u64));
+
+    group.bench_function("frames", |b| {
+        b.iter(|| frames(black_box(&capture)).filter(Result::is_ok).count())
+    });
+
+    group.bench_function("decoder_frames", |b| {
+        b.iter(|| {
+            let mut decoder = Decoder::<4096>::new();
+            let mut count = 0;
+            for chunk in black_box(&capture).chunks(512) {
+                decoder.push(chunk);
+                while let Ok(Some(frame)) = decoder.decode_frame() {
+                    count += frame.data.len();
+                }
+            }
+            count
+        })
+    });
+
+    group.bench_function("decoder_messages", |b| {
+        b.iter(|| {
+            let mut decoder = Decoder::<4096>::new();
+            let mut count = 0;
+            for chunk in black_box(&capture).chunks(512) {
+                decoder.push(chunk);
+                while let Ok(Some(msg)) = decoder.decode() {
+                    count += msg.data().len();
+                }
+            }
+            count
+        })
+    });
+
+    group.bench_function("frames_data", |b| {
+        b.iter(|| {
+            frames(black_box(&capture))
+                .filter_map(|f| f.ok()?.decode().ok())
+                .count()
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, decode);
+criterion_main!(benches);
@@ -0,0 +1,79 @@
+-- rawzeo.lua
+--
+-- A Wireshark dissector for the PCAP files exported by rawzeo.
+--
+-- Copy it to the personal Lua plugins folder of Wireshark. The packets use
+-- the link-layer type DLT_USER0 (147), and start with a byte indicating the
+-- kind of segment, followed by the raw bytes as received.
+
+local zeo = Proto("zeo", "Zeo raw data")
+
+local kinds = {
+    [0] = "Frame",
+    [1] = "Skipped",
+    [2] = "Invalid length",
+    [3] = "Invalid checksum",
+    [4] = "Unsupported protocol",
+}
+
+local datatypes = {
+    [0x00] = "Event",
+    [0x02] = "SliceEnd",
+    [0x03] = "Version",
+    [0x80] = "Waveform",
+    [0x83] = "FrequencyBins",
+    [0x84] = "Sqi",
+    [0x8A] = "ZeoTimestamp",
+    [0x97] = "Impedance",
+    [0x9C] = "BadSignal",
+    [0x9D] = "SleepStage",
+}
+
+local f = zeo.fields
+f.kind = ProtoField.uint8("zeo.kind", "Kind", base.DEC, kinds)
+f.start = ProtoField.char("zeo.start", "Start")
+f.protocol = ProtoField.char("zeo.protocol", "Protocol version")
+f.checksum = ProtoField.uint8("zeo.checksum", "Checksum", base.HEX)
+f.len = ProtoField.uint16("zeo.len", "Length", base.DEC)
+f.inv_len = ProtoField.uint16("zeo.inv_len", "Inverse length", base.HEX)
+f.time_low = ProtoField.uint8("zeo.time_low", "Time low byte", base.DEC)
+f.subsec = ProtoField.uint16("zeo.subsec", "Subsecond", base.DEC)
+f.seqnum = ProtoField.uint8("zeo.seqnum", "Sequence number", base.DEC)
+f.datatype = ProtoField.uint8("zeo.datatype", "Datatype", base.HEX, datatypes)
+f.data = ProtoField.bytes("zeo.data", "Data")
+f.skipped = ProtoField.bytes("zeo.skipped", "Skipped bytes")
+
+function zeo.dissector(buf, pinfo, tree)
+    pinfo.cols.protocol = "ZEO"
+    local kind = buf(0, 1):uint()
+    local t = tree:add(zeo, buf())
+    t:add(f.kind, buf(0, 1))
+
+    if kind ~= 0 then
+        t:add(f.skipped, buf(1))
+        pinfo.cols.info = kinds[kind] or "Unknown"
+        if kind >= 2 then
+            t:add_expert_info(PI_MALFORMED, PI_WARN, kinds[kind])
+        end
+        return
+    end
+
+    local b = buf(1)
+    t:add(f.start, b(0, 1))
+    t:add(f.protocol, b(1, 1))
+    t:add(f.checksum, b(2, 1))
+    t:add_le(f.len, b(3, 2))
+    t:add_le(f.inv_len, b(5, 2))
+    t:add(f.time_low, b(7, 1))
+    t:add_le(f.subsec, b(8, 2))
+    t:add(f.seqnum, b(10, 1))
+    t:add(f.datatype, b(11, 1))
+    if b:len() > 12 then
+        t:add(f.data, b(12))
+    end
+    local datatype = b(11, 1):uint()
+    pinfo.cols.info = string.format("seq=%d %s", b(10, 1):uint(),
+        datatypes[datatype] or string.format("0x%02X", datatype))
+end
+
+DissectorTable.get("wtap_encap"):add(wtap.USER0, zeo)
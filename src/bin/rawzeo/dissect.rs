@@ -0,0 +1,332 @@
+// rawzeo::bin::dissect
+//
+//! The frame dissector.
+//
+
+use std::{
+    fmt::Write as _,
+    fs,
+    io::{self, Read, Write},
+    path::Path,
+};
+
+use rawzeo::{Data, DataType, HEADER_LEN, MAX_DATA_LEN, PCAP_LINKTYPE, PROTOCOL_VERSIONS};
+
+/// The number of data bytes shown on each line.
+const ROW_LEN: usize = 16;
+
+/// Returns the bytes of the `input`, which can be a hex string, the path of
+/// a file containing either a hex dump, a raw capture or an exported PCAP,
+/// or `-` for stdin.
+pub fn read_input(input: &str) -> io::Result<Vec<u8>> {
+    let bytes = if input == "-" {
+        let mut bytes = vec![];
+        io::stdin().read_to_end(&mut bytes)?;
+        bytes
+    } else if Path::new(input).is_file() {
+        fs::read(input)?
+    } else {
+        return parse_hex(input)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid hex string."));
+    };
+    if let Some(bytes) = parse_pcap(&bytes) {
+        return Ok(bytes);
+    }
+    // a text file is parsed as hex, anything else as a raw capture
+    Ok(std::str::from_utf8(&bytes)
+        .ok()
+        .and_then(parse_hex)
+        .unwrap_or(bytes))
+}
+
+/// Returns the received bytes of a PCAP file exported by [`PcapExporter`],
+/// in the order they were received, or `None` if it isn't one.
+///
+/// [`PcapExporter`]: rawzeo::PcapExporter
+fn parse_pcap(pcap: &[u8]) -> Option<Vec<u8>> {
+    let u32_at = |i: usize| Some(u32::from_le_bytes(pcap.get(i..i + 4)?.try_into().ok()?));
+    if u32_at(0)? != 0xA1B2_C3D4 || u32_at(20)? != PCAP_LINKTYPE {
+        return None;
+    }
+    let mut bytes = vec![];
+    let mut offset = 24;
+    // each packet has a 16 bytes header, and then the kind of segment,
+    // and a truncated last packet is ignored
+    while let Some(len) = u32_at(offset + 8) {
+        let packet = match pcap.get(offset + 16..offset + 16 + len as usize) {
+            Some(packet) => packet,
+            None => break,
+        };
+        bytes.extend(packet.get(1..).unwrap_or_default());
+        offset += 16 + len as usize;
+    }
+    Some(bytes)
+}
+
+/// Parses a hex string, ignoring whitespace, `0x` prefixes, and the
+/// `[N B]:` length prefixes of the logged hex dumps.
+fn parse_hex(text: &str) -> Option<Vec<u8>> {
+    let mut digits = String::new();
+    for word in text.split_whitespace() {
+        if word.starts_with('[') || word == "B]:" {
+            continue;
+        }
+        let word = word.trim_start_matches("0x").trim_end_matches(',');
+        if !word.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        digits.push_str(word);
+    }
+    if digits.is_empty() || digits.len() % 2 != 0 {
+        return None;
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Prints each frame found in the `bytes`, dissected field by field.
+///
+/// The fields with problems are marked with `!!`.
+pub fn dissect(bytes: &[u8], out: &mut impl Write) -> io::Result<()> {
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let rest = &bytes[offset..];
+        let Some(start) = rest
+            .windows(2)
+            .position(|w| w[0] == b'A' && w[1].is_ascii_digit())
+        else {
+            writeln!(
+                out,
+                "{offset:#06X}: {} trailing bytes: {}\n",
+                rest.len(),
+                hex(rest)
+            )?;
+            break;
+        };
+        if start > 0 {
+            let skipped = &rest[..start];
+            writeln!(
+                out,
+                "{offset:#06X}: !! {} skipped bytes: {}\n",
+                start,
+                hex(skipped)
+            )?;
+            offset += start;
+        }
+        offset += dissect_frame(&bytes[offset..], offset, out)?;
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Prints a single frame starting at the beginning of `bytes`,
+/// found at the given `offset`.
+///
+/// Returns the number of bytes to advance before looking for the next frame.
+fn dissect_frame(b: &[u8], offset: usize, out: &mut impl Write) -> io::Result<usize> {
+    writeln!(out, "{offset:#06X}: frame")?;
+
+    field(out, "A", &b[..1], "start")?;
+    let protocol = b[1] - b'0';
+    if PROTOCOL_VERSIONS.contains(&protocol) {
+        field(out, "n", &b[1..2], &format!["protocol version {protocol}"])?;
+    } else {
+        field(
+            out,
+            "n",
+            &b[1..2],
+            &format!["!! unsupported protocol version {protocol}"],
+        )?;
+    }
+    if b.len() < HEADER_LEN {
+        writeln!(out, "  !! truncated header: {}", hex(&b[2..]))?;
+        return Ok(b.len());
+    }
+
+    let checksum = b[2];
+    let len = u16::from_le_bytes([b[3], b[4]]);
+    let inv_len = u16::from_le_bytes([b[5], b[6]]);
+    let valid_len = len == !inv_len && len != 0 && len as usize - 1 <= MAX_DATA_LEN;
+    let data_len = (len as usize).saturating_sub(1);
+    let data = &b[HEADER_LEN..b.len().min(HEADER_LEN + data_len)];
+    let sum = data.iter().fold(b[11], |sum, b| sum.wrapping_add(*b));
+
+    let complete = valid_len && data.len() == data_len;
+    if !complete {
+        field(out, "c", &b[2..3], &format!["checksum {checksum:#04X}"])?;
+    } else if sum == checksum {
+        field(
+            out,
+            "c",
+            &b[2..3],
+            &format!["checksum {checksum:#04X}, computed {sum:#04X}"],
+        )?;
+    } else {
+        let desc = format!["!! checksum {checksum:#04X}, computed {sum:#04X}"];
+        field(out, "c", &b[2..3], &desc)?;
+    }
+    field(
+        out,
+        "ll",
+        &b[3..5],
+        &format!["length {len} ({data_len} data bytes)"],
+    )?;
+    if len != !inv_len {
+        field(
+            out,
+            "LL",
+            &b[5..7],
+            &format!["!! inverse length {inv_len}, expected {}", !len],
+        )?;
+    } else if !valid_len {
+        field(
+            out,
+            "LL",
+            &b[5..7],
+            &format!["!! length out of range (max {MAX_DATA_LEN} data bytes)"],
+        )?;
+    } else {
+        field(out, "LL", &b[5..7], &format!["inverse length {inv_len}"])?;
+    }
+    field(out, "T", &b[7..8], &format!["time low byte {}", b[7]])?;
+    field(
+        out,
+        "tt",
+        &b[8..10],
+        &format!["subsecond {}", u16::from_le_bytes([b[8], b[9]])],
+    )?;
+    field(out, "s", &b[10..11], &format!["sequence number {}", b[10]])?;
+    let datatype = DataType::from(b[11]);
+    match datatype {
+        DataType::Invalid(_) => field(out, "i", &b[11..12], "!! unknown datatype")?,
+        _ => field(out, "i", &b[11..12], &format!["datatype {datatype}"])?,
+    }
+
+    if !valid_len {
+        return Ok(2);
+    }
+    for (i, row) in data.chunks(ROW_LEN).enumerate() {
+        field(out, if i == 0 { "d" } else { "" }, row, "")?;
+    }
+    if data.len() < data_len {
+        writeln!(
+            out,
+            "  !! truncated data: {} of {data_len} bytes",
+            data.len()
+        )?;
+        return Ok(b.len());
+    }
+    match Data::decode(datatype, data) {
+        Ok(data) => writeln!(out, "  =   {data:?}")?,
+        Err(e) => writeln!(out, "  !!  {e}")?,
+    }
+    Ok(HEADER_LEN + data_len)
+}
+
+/// Prints a field with its `name`, `bytes` and description.
+fn field(out: &mut impl Write, name: &str, bytes: &[u8], desc: &str) -> io::Result<()> {
+    let line = format!["  {name:<3} {:<12} {desc}", hex(bytes)];
+    writeln!(out, "{}", line.trim_end())
+}
+
+/// Returns the `bytes` in hexadecimal, separated by spaces.
+fn hex(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len() * 3);
+    for b in bytes {
+        let _ = write!(s, "{b:02X} ");
+    }
+    s.pop();
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rawzeo::PcapExporter;
+    use std::time::SystemTime;
+
+    #[test]
+    fn parse() {
+        let hex = "[6 B]: 41 34 0x0A, 05 00\nFA ff";
+        assert_eq![
+            parse_hex(hex),
+            Some(vec![0x41, 0x34, 0x0A, 0x05, 0x00, 0xFA, 0xFF])
+        ];
+        assert_eq![parse_hex("41 3"), None];
+        assert_eq![parse_hex("41 zz"), None];
+        assert_eq![parse_hex(""), None];
+    }
+
+    #[test]
+    fn pcap() {
+        let frame = parse_hex("41 34 88 05 00 FA FF 12 00 00 07 84 04 00 00 00").unwrap();
+        let header = rawzeo::frames(&frame).next().unwrap().unwrap().header;
+        let mut pcap = vec![];
+        let mut exporter = PcapExporter::new(&mut pcap).unwrap();
+        exporter
+            .write_skipped(SystemTime::UNIX_EPOCH, b"junk", None)
+            .unwrap();
+        exporter
+            .write_frame(SystemTime::UNIX_EPOCH, &header, &frame[HEADER_LEN..])
+            .unwrap();
+        exporter.flush().unwrap();
+
+        let bytes = [&b"junk"[..], &frame].concat();
+        assert_eq![parse_pcap(&pcap), Some(bytes)];
+        // a truncated last packet is ignored
+        assert_eq![parse_pcap(&pcap[..pcap.len() - 1]), Some(b"junk".to_vec())];
+        assert_eq![parse_pcap(&frame), None];
+    }
+
+    #[test]
+    fn fields() {
+        let frames = "EE EE
+            41 34 88 05 00 FA FF 12 00 00 07 84 04 00 00 00
+            41 34 00 02 00 FD FF 12 00 00 08 42 01
+            41 34 00 05 00 FA FF";
+        let bytes = parse_hex(frames).unwrap();
+        let mut out = vec![];
+        dissect(&bytes, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq![
+            out.lines().collect::<Vec<_>>(),
+            [
+                "0x0000: !! 2 skipped bytes: EE EE",
+                "",
+                "0x0002: frame",
+                "  A   41           start",
+                "  n   34           protocol version 4",
+                "  c   88           checksum 0x88, computed 0x88",
+                "  ll  05 00        length 5 (4 data bytes)",
+                "  LL  FA FF        inverse length 65530",
+                "  T   12           time low byte 18",
+                "  tt  00 00        subsecond 0",
+                "  s   07           sequence number 7",
+                "  i   84           datatype Sqi",
+                "  d   04 00 00 00",
+                "  =   Sqi(4)",
+                "",
+                "0x0012: frame",
+                "  A   41           start",
+                "  n   34           protocol version 4",
+                "  c   00           !! checksum 0x00, computed 0x43",
+                "  ll  02 00        length 2 (1 data bytes)",
+                "  LL  FD FF        inverse length 65533",
+                "  T   12           time low byte 18",
+                "  tt  00 00        subsecond 0",
+                "  s   08           sequence number 8",
+                "  i   42           !! unknown datatype",
+                "  d   01",
+                "  !!  Bad datatype: 0x42.",
+                "",
+                "0x001F: frame",
+                "  A   41           start",
+                "  n   34           protocol version 4",
+                "  !! truncated header: 00 05 00 FA FF",
+                "",
+            ]
+        ];
+    }
+}
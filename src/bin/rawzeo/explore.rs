@@ -0,0 +1,242 @@
+// rawzeo::bin::explore
+//
+//! The protocol explorer.
+//
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use log::error;
+use rawzeo::{Data, DataType, EventType, SleepStages};
+
+use crate::sink::{Record, Sink};
+
+/// The minimum time between the rewrites of the report.
+const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A histogram of values.
+type Histogram<K> = BTreeMap<K, u64>;
+
+/// Collects the distributions of the values seen in the frames,
+/// in order to help reverse-engineer the undocumented parts of the protocol.
+///
+/// The distributions are periodically written to `explore.txt`,
+/// and the raw payload of every frame that couldn't be decoded,
+/// or that contains an unknown event or sleep stage, is appended to `unknown.txt`, both inside the given directory.
+pub struct ExploreSink {
+    report: PathBuf,
+    reported: Instant,
+    unknown: BufWriter<File>,
+
+    datatypes: Histogram<u8>,
+    lengths: BTreeMap<u8, Histogram<usize>>,
+    subsecs: Histogram<u16>,
+    events: Histogram<u32>,
+    // the milliseconds between received frames of the same datatype
+    intervals: BTreeMap<u8, Histogram<u128>>,
+    // the seconds of Zeo time between frames of the same datatype
+    zeo_intervals: BTreeMap<u8, Histogram<i64>>,
+    last: BTreeMap<u8, (SystemTime, u32)>,
+}
+
+impl ExploreSink {
+    /// Creates the explorer files inside the existing `dir`.
+    pub fn create<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        let dir = dir.as_ref();
+        let mut unknown = BufWriter::new(File::create(dir.join("unknown.txt"))?);
+        writeln!(
+            unknown,
+            "# received time subsec seqnum protocol version datatype len error payload"
+        )?;
+        Ok(Self {
+            report: dir.join("explore.txt"),
+            reported: Instant::now(),
+            unknown,
+            datatypes: BTreeMap::new(),
+            lengths: BTreeMap::new(),
+            subsecs: BTreeMap::new(),
+            events: BTreeMap::new(),
+            intervals: BTreeMap::new(),
+            zeo_intervals: BTreeMap::new(),
+            last: BTreeMap::new(),
+        })
+    }
+
+    /// Writes all the distributions to the report file.
+    fn write_report(&mut self) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(&self.report)?);
+
+        writeln!(out, "# datatypes: count")?;
+        for (dt, count) in &self.datatypes {
+            writeln!(out, "{}: {count}", name(*dt))?;
+        }
+        writeln!(out, "\n# payload lengths per datatype: length: count")?;
+        for (dt, lengths) in &self.lengths {
+            write_histogram(&mut out, &name(*dt), lengths)?;
+        }
+        writeln!(out, "\n# subseconds: count")?;
+        write_histogram(&mut out, "subsec", &self.subsecs)?;
+        writeln!(
+            out,
+            "\n# received intervals per datatype: milliseconds: count"
+        )?;
+        for (dt, intervals) in &self.intervals {
+            write_histogram(&mut out, &name(*dt), intervals)?;
+        }
+        writeln!(out, "\n# zeo time intervals per datatype: seconds: count")?;
+        for (dt, intervals) in &self.zeo_intervals {
+            write_histogram(&mut out, &name(*dt), intervals)?;
+        }
+        writeln!(out, "\n# event codes: count")?;
+        for (code, count) in &self.events {
+            let event = EventType::from(*code as u8);
+            writeln!(out, "0x{code:08X} {event}: {count}")?;
+        }
+        out.flush()
+    }
+}
+
+impl Sink for ExploreSink {
+    fn name(&self) -> &str {
+        "explore"
+    }
+    fn write(&mut self, record: &Record) -> io::Result<()> {
+        let msg = &record.msg;
+        let dt = u8::from(msg.datatype);
+        let data = msg.data();
+
+        count(&mut self.datatypes, dt);
+        count(self.lengths.entry(dt).or_default(), data.len());
+        count(&mut self.subsecs, msg.subsec);
+        if msg.datatype == DataType::Event {
+            let mut code = [0; 4];
+            let len = data.len().min(4);
+            code[..len].copy_from_slice(&data[..len]);
+            count(&mut self.events, u32::from_le_bytes(code));
+        }
+        if let Some((received, time)) = self.last.insert(dt, (record.received, msg.time)) {
+            let elapsed = record.received.duration_since(received).unwrap_or_default();
+            count(self.intervals.entry(dt).or_default(), elapsed.as_millis());
+            let secs = msg.time as i64 - time as i64;
+            count(self.zeo_intervals.entry(dt).or_default(), secs);
+        }
+
+        let error = match &record.data {
+            Err(e) => Some(e.to_string()),
+            Ok(Data::Event(EventType::Invalid(b))) => Some(format!["Unknown event: 0x{b:02X}."]),
+            Ok(Data::SleepStage(SleepStages::Invalid(b))) => {
+                Some(format!["Unknown sleep stage: 0x{b:02X}."])
+            }
+            Ok(_) => None,
+        };
+        if let Some(error) = error {
+            let received = record
+                .received
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0.0, |d| d.as_secs_f64());
+            write!(
+                self.unknown,
+                "{received:.3} {} {} {} {} {} 0x{dt:02X} {} \"{error}\" ",
+                msg.time,
+                msg.subsec,
+                msg.seqnum,
+                msg.protocol,
+                msg.version,
+                data.len()
+            )?;
+            for b in data {
+                write!(self.unknown, "{b:02X}")?;
+            }
+            writeln!(self.unknown)?;
+        }
+        Ok(())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.unknown.flush()?;
+        if self.reported.elapsed() >= REPORT_INTERVAL {
+            self.reported = Instant::now();
+            self.write_report()?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ExploreSink {
+    fn drop(&mut self) {
+        if let Err(e) = self.write_report() {
+            error!("Failed to write the explorer report. Error: {}", e);
+        }
+    }
+}
+
+/// Counts one more occurrence of a `value` in a `histogram`.
+fn count<K: Ord>(histogram: &mut Histogram<K>, value: K) {
+    *histogram.entry(value).or_default() += 1;
+}
+
+/// Returns the name of a datatype byte, including its value.
+fn name(datatype: u8) -> String {
+    format!["0x{datatype:02X} {}", DataType::from(datatype)]
+}
+
+/// Writes a `histogram` as a line for each value, preceded by a `label`.
+fn write_histogram<K: std::fmt::Display>(
+    out: &mut impl Write,
+    label: &str,
+    histogram: &Histogram<K>,
+) -> io::Result<()> {
+    for (value, count) in histogram {
+        writeln!(out, "{label}  {value}: {count}")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn unknown() {
+        let dir = std::env::temp_dir().join(format!["rawzeo-explore-{}", std::process::id()]);
+        fs::create_dir_all(&dir).unwrap();
+        let mut sink = ExploreSink::create(&dir).unwrap();
+        let t = 1_675_288_800;
+        for record in [
+            Record::with_payload(t, DataType::Event, &[0x05, 0, 0, 0]),
+            Record::with_payload(t, DataType::from(0x99), &[0xAB, 0xCD]),
+            Record::with_payload(t + 1, DataType::Event, &[0x42, 0, 0, 0]),
+            Record::with_payload(t + 2, DataType::SleepStage, &[0x09, 0, 0, 0]),
+        ] {
+            sink.write(&record).unwrap();
+        }
+        sink.flush().unwrap();
+
+        let unknown = fs::read_to_string(dir.join("unknown.txt")).unwrap();
+        let lines: Vec<_> = unknown.lines().skip(1).collect();
+        assert_eq![
+            lines,
+            [
+                format!["0.000 {t} 0 0 4 3 0x99 2 \"Bad datatype: 0x99.\" ABCD"],
+                format![
+                    "0.000 {} 0 0 4 3 0x00 4 \"Unknown event: 0x42.\" 42000000",
+                    t + 1
+                ],
+                format![
+                    "0.000 {} 0 0 4 3 0x9D 4 \"Unknown sleep stage: 0x09.\" 09000000",
+                    t + 2
+                ],
+            ]
+        ];
+        drop(sink);
+        assert![fs::read_to_string(dir.join("explore.txt"))
+            .unwrap()
+            .contains("0x00000042 Invalid(66): 1")];
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,68 @@
+// rawzeo::bin::hook
+//
+//! The hook command run at the start and end of each night.
+//
+
+use std::{io, process::Command, thread};
+
+use log::{error, info, warn};
+use rawzeo::{Data, EventType};
+
+use crate::sink::{Record, Sink};
+
+/// Runs a shell command on each event starting or ending a night.
+///
+/// A night starts with a `NightStart` or `HeadbandUnDocked` event, and ends
+/// with a `NightEnd` or `HeadbandDocked` event, like in [`NightlySink`].
+/// The command receives the environment variables `RAWZEO_NIGHT`, either
+/// `start` or `end`, `RAWZEO_EVENT`, the name of the event, and `RAWZEO_TIME`,
+/// its unix time. It runs in the background, without blocking the sink.
+///
+/// [`NightlySink`]: crate::nightly::NightlySink
+pub struct HookSink {
+    command: String,
+}
+
+impl HookSink {
+    /// Returns a new sink running the shell `command`.
+    pub fn new(command: &str) -> Self {
+        Self {
+            command: command.to_string(),
+        }
+    }
+}
+
+impl Sink for HookSink {
+    fn name(&self) -> &str {
+        "hook"
+    }
+    fn write(&mut self, record: &Record) -> io::Result<()> {
+        use EventType::*;
+        let event = match &record.data {
+            Ok(Data::Event(event)) => *event,
+            _ => return Ok(()),
+        };
+        let night = match event {
+            NightStart | HeadbandUnDocked => "start",
+            NightEnd | HeadbandDocked => "end",
+            _ => return Ok(()),
+        };
+        info!("Running the hook of the {event} event.");
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .env("RAWZEO_NIGHT", night)
+            .env("RAWZEO_EVENT", event.to_string())
+            .env("RAWZEO_TIME", record.msg.time.to_string())
+            .spawn()?;
+        // wait for it in the background, so that it doesn't linger as a zombie
+        thread::Builder::new()
+            .name("hook".into())
+            .spawn(move || match child.wait() {
+                Ok(status) if status.success() => (),
+                Ok(status) => warn!("The hook of the {event} event failed with {status}."),
+                Err(e) => error!("Failed to wait for the hook. Error: {}", e),
+            })?;
+        Ok(())
+    }
+}
@@ -0,0 +1,970 @@
+// rawzeo::bin
+//
+//! Read raw data from Zeo headband.
+//
+
+use std::{
+    env, fs, io,
+    net::TcpStream,
+    path::{Path, PathBuf},
+    sync::atomic::Ordering,
+    time::Duration,
+};
+
+use log::{error, info, LevelFilter, Log, Metadata, Record};
+use serde::Deserialize;
+
+mod dissect;
+mod explore;
+mod hook;
+mod metrics;
+mod mqtt;
+mod nightly;
+mod notify;
+mod osc;
+mod pipeline;
+mod port;
+mod reader;
+mod report;
+mod server;
+mod sink;
+#[cfg(unix)]
+mod tee;
+#[cfg(feature = "websocket")]
+mod websocket;
+
+use explore::ExploreSink;
+use metrics::Metrics;
+use nightly::{Create, NightlySink};
+use pipeline::Tap;
+use reader::Reader;
+use server::{Broadcast, JsonServerSink};
+use sink::{CsvSink, DebugSink, EdfSink, JsonSink, PcapSink, Sink, SinkThread};
+
+/// The timeout of the reads from a TCP stream.
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// The maximum number of chunks queued between the reader and the decoder.
+const READER_QUEUE_LEN: usize = 1024;
+
+/// The maximum number of decoded frames queued for each sink.
+const SINK_QUEUE_LEN: usize = 4096;
+
+/// The maximum number of chunks queued for the pseudo-terminal.
+#[cfg(unix)]
+const TEE_QUEUE_LEN: usize = 1024;
+
+fn main() {
+    let args = Args::parse();
+
+    if let Err(e) = log::set_logger(&StderrLogger) {
+        eprintln!("Failed to set the logger. Error: {}", e);
+    }
+    log::set_max_level(args.log_level());
+    if let Some(path) = &args.config {
+        info!("Loaded the configuration from \"{}\"", path.display());
+    }
+
+    let printed = match args.command {
+        Command::Record => return run(&args, None),
+        Command::Replay | Command::Export => return run(&args, Some(args.read_input())),
+        Command::Report => report::report(&args.read_input(), &mut io::stdout().lock()),
+        Command::Inspect => dissect::dissect(&args.read_input(), &mut io::stdout().lock()),
+        Command::Ports => port::list(&mut io::stdout().lock()),
+    };
+    if let Err(e) = printed {
+        error!(
+            "Failed to print the output of {}. Error: {}",
+            args.command.name(),
+            e
+        );
+        ::std::process::exit(1);
+    }
+}
+
+/// Decodes the live data, or the captured `input`, into the outputs.
+fn run(args: &Args, input: Option<Vec<u8>>) {
+    let mut sinks: Vec<Box<dyn Sink>> = vec![];
+    if args.json {
+        sinks.push(Box::new(JsonSink::new(io::stdout())));
+    } else if !args.daemon && args.command != Command::Export {
+        sinks.push(Box::new(DebugSink));
+    }
+    if let Some(dir) = &args.explore {
+        match args.output("explore", dir, create_explore) {
+            Ok(explore) => sinks.push(explore),
+            Err(e) => {
+                error!(
+                    "Failed to create the explorer files in \"{}\". Error: {}",
+                    dir, e
+                );
+                ::std::process::exit(1);
+            }
+        }
+    }
+    if let Some(path) = &args.pcap {
+        match args.output("pcap", path, create_pcap) {
+            Ok(pcap) => sinks.push(pcap),
+            Err(e) => {
+                error!("Failed to create \"{}\". Error: {}", path, e);
+                ::std::process::exit(1);
+            }
+        }
+    }
+    if let Some(path) = &args.edf {
+        match args.output("edf", path, create_edf) {
+            Ok(edf) => sinks.push(edf),
+            Err(e) => {
+                error!("Failed to create \"{}\". Error: {}", path, e);
+                ::std::process::exit(1);
+            }
+        }
+    }
+    if let Some(dir) = &args.csv {
+        match args.output("csv", dir, create_csv) {
+            Ok(csv) => sinks.push(csv),
+            Err(e) => {
+                error!(
+                    "Failed to create the CSV files in \"{}\". Error: {}",
+                    dir, e
+                );
+                ::std::process::exit(1);
+            }
+        }
+    }
+    #[cfg(feature = "sqlite")]
+    if let Some(path) = &args.sqlite {
+        // a single database keeps all the nights, even in daemon mode
+        match sink::SqliteSink::open(path) {
+            Ok(sqlite) => sinks.push(Box::new(sqlite)),
+            Err(e) => {
+                error!("Failed to open the database \"{}\". Error: {}", path, e);
+                ::std::process::exit(1);
+            }
+        }
+    }
+    if let Some(addr) = &args.serve_json {
+        let broadcast = Broadcast::listen("json", addr).unwrap_or_else(|e| {
+            error!("Failed to listen on \"{}\". Error: {}", addr, e);
+            ::std::process::exit(1);
+        });
+        sinks.push(Box::new(JsonServerSink::new(broadcast)));
+    }
+    #[cfg(feature = "websocket")]
+    if let Some(addr) = &args.websocket {
+        let broadcast = websocket::listen(addr).unwrap_or_else(|e| {
+            error!("Failed to listen on \"{}\". Error: {}", addr, e);
+            ::std::process::exit(1);
+        });
+        sinks.push(Box::new(websocket::WebSocketSink::new(broadcast)));
+    }
+    if let Some(addr) = &args.osc {
+        let prefix = args.osc_prefix.as_deref().unwrap_or(osc::OSC_PREFIX);
+        match osc::OscSink::new(addr, prefix, args.osc_waveform) {
+            Ok(osc) => sinks.push(Box::new(osc)),
+            Err(e) => {
+                error!("Failed to send OSC to \"{}\". Error: {}", addr, e);
+                ::std::process::exit(1);
+            }
+        }
+    }
+    if let Some(command) = &args.hook {
+        sinks.push(Box::new(hook::HookSink::new(command)));
+    }
+    if let Some(addr) = &args.mqtt {
+        let topic = args.mqtt_topic.as_deref().unwrap_or(mqtt::MQTT_TOPIC);
+        match mqtt::MqttSink::connect(addr, topic, args.mqtt_discovery) {
+            Ok(mqtt) => sinks.push(Box::new(mqtt)),
+            Err(e) => {
+                error!("Failed to connect to the MQTT broker. Error: {}", e);
+                ::std::process::exit(1);
+            }
+        }
+    }
+    // a capture is decoded as fast as the sinks can write it, dropping nothing
+    let spawn = match input {
+        Some(_) => SinkThread::spawn_blocking,
+        None => SinkThread::spawn,
+    };
+    let sinks = sinks
+        .into_iter()
+        .map(|sink| spawn(sink, SINK_QUEUE_LEN))
+        .collect::<io::Result<Vec<_>>>()
+        .unwrap_or_else(|e| {
+            error!("Failed to spawn the sinks. Error: {}", e);
+            ::std::process::exit(1);
+        });
+
+    let metrics = Metrics::new();
+    if let Some(addr) = &args.metrics {
+        if let Err(e) = metrics::serve(addr, metrics.clone()) {
+            error!("Failed to listen on \"{}\". Error: {}", addr, e);
+            ::std::process::exit(1);
+        }
+    }
+
+    let mut taps: Vec<Box<dyn Tap>> = vec![];
+    if args.pty {
+        #[cfg(unix)]
+        match tee::PtyTee::spawn(TEE_QUEUE_LEN) {
+            Ok(tee) => {
+                info!("Re-publishing the raw data on {}", tee.path());
+                taps.push(Box::new(tee));
+            }
+            Err(e) => {
+                error!("Failed to create the pseudo-terminal. Error: {}", e);
+                ::std::process::exit(1);
+            }
+        }
+        #[cfg(not(unix))]
+        {
+            error!("Pseudo-terminals are not supported on this platform.");
+            ::std::process::exit(1);
+        }
+    }
+
+    let mut raw_server = None;
+    if let Some(addr) = &args.serve {
+        let broadcast = Broadcast::listen("raw", addr).unwrap_or_else(|e| {
+            error!("Failed to listen on \"{}\". Error: {}", addr, e);
+            ::std::process::exit(1);
+        });
+        raw_server = Some(broadcast.clone());
+        taps.push(Box::new(broadcast));
+    }
+
+    let reader = if let Some(bytes) = input {
+        Reader::spawn_blocking(Box::new(io::Cursor::new(bytes)), READER_QUEUE_LEN)
+    } else if let Some(addr) = &args.connect {
+        match TcpStream::connect(addr) {
+            Ok(stream) => {
+                info!("Receiving data from {}:", addr);
+                // so that the reader notices when it's asked to stop
+                if let Err(e) = stream.set_read_timeout(Some(READ_TIMEOUT)) {
+                    error!("Failed to set the read timeout. Error: {}", e);
+                }
+                Reader::spawn(Box::new(stream), READER_QUEUE_LEN)
+            }
+            Err(e) => {
+                error!("Failed to connect to \"{}\". Error: {}", addr, e);
+                ::std::process::exit(1);
+            }
+        }
+    } else {
+        let port = args.port.clone().unwrap_or_else(|| port::PORT.into());
+        let serial_number = args.serial_number.clone();
+        let open = move || port::open(&port, serial_number.as_deref());
+        Reader::spawn_reopening(Box::new(open), READER_QUEUE_LEN)
+    };
+
+    let reader = reader.unwrap_or_else(|e| {
+        error!("Failed to spawn the reader. Error: {}", e);
+        ::std::process::exit(1);
+    });
+
+    // the first signal stops the reader, which ends the pipeline,
+    // and the second one forces the exit
+    let stop = reader.stop.clone();
+    let handler = move || {
+        if stop.swap(true, Ordering::Relaxed) {
+            eprintln!("Forcing the exit.");
+            ::std::process::exit(130);
+        }
+        info!("Stopping… (again to force the exit)");
+    };
+    if let Err(e) = ctrlc::set_handler(handler) {
+        error!("Failed to set the signal handler. Error: {}", e);
+    }
+
+    notify::notify("READY=1");
+    if let Some(interval) = notify::watchdog() {
+        if let Err(e) = notify::spawn_watchdog(interval, reader.heartbeat.clone()) {
+            error!("Failed to spawn the watchdog. Error: {}", e);
+        }
+    }
+
+    // validated when parsing the arguments
+    let filter = args.mains_filter.and_then(pipeline::MainsFilter::new);
+    pipeline::run(reader.chunks, &taps, &sinks, &metrics, filter);
+    notify::notify("STOPPING=1");
+
+    // finish writing all the outputs before summarizing
+    for sink in sinks {
+        let (name, dropped) = (sink.name().to_string(), sink.dropped());
+        sink.join();
+        if dropped > 0 {
+            info!("{dropped} records dropped by the {name} sink.");
+        }
+    }
+    let dropped = reader.dropped.load(Ordering::Relaxed);
+    if dropped > 0 {
+        info!("{dropped} bytes dropped by the reader.");
+    }
+    if let Some(dropped) = raw_server.map(|s| s.dropped()).filter(|d| *d > 0) {
+        info!("{dropped} chunks dropped by the raw server.");
+    }
+
+    let mut summary = String::new();
+    // writing to a string can't fail
+    let _ = metrics.write_summary(&mut summary);
+    // printed even when the logs are quieted, like the output of a command
+    eprintln!("{summary}");
+}
+
+/// Creates the explorer sink in `dir`.
+fn create_explore(dir: &Path) -> io::Result<Box<dyn Sink>> {
+    fs::create_dir_all(dir)?;
+    Ok(Box::new(ExploreSink::create(dir)?))
+}
+
+/// Creates the PCAP sink at `path`.
+fn create_pcap(path: &Path) -> io::Result<Box<dyn Sink>> {
+    Ok(Box::new(PcapSink::create(path)?))
+}
+
+/// Creates the EDF sink at `path`.
+fn create_edf(path: &Path) -> io::Result<Box<dyn Sink>> {
+    Ok(Box::new(EdfSink::create(path)?))
+}
+
+/// Creates the CSV sink in `dir`.
+fn create_csv(dir: &Path) -> io::Result<Box<dyn Sink>> {
+    fs::create_dir_all(dir)?;
+    Ok(Box::new(CsvSink::create(dir)?))
+}
+
+/// The command line arguments, over those of the configuration file.
+///
+/// The configuration file uses the names of the long options as its keys.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct Args {
+    /// The command to run.
+    #[serde(skip)]
+    command: Command,
+
+    /// The capture to read, for the commands that take one.
+    #[serde(skip)]
+    input: Option<String>,
+
+    /// The configuration file that was loaded, if any.
+    #[serde(skip)]
+    config: Option<PathBuf>,
+
+    /// The directory where to export the CSV tables.
+    csv: Option<String>,
+
+    /// The file where to export the waveform as EDF+.
+    edf: Option<String>,
+
+    /// Whether to print each decoded message as a line of JSON.
+    json: bool,
+
+    /// The file where to export the frames as PCAP.
+    pcap: Option<String>,
+
+    /// The SQLite database where to store the decoded data.
+    #[cfg(feature = "sqlite")]
+    sqlite: Option<String>,
+
+    /// The serial port to read from.
+    port: Option<String>,
+
+    /// The serial number of the USB serial port to read from.
+    serial_number: Option<String>,
+
+    /// The address of a raw data server to read from, instead of the serial port.
+    connect: Option<String>,
+
+    /// The address where to serve the raw data.
+    serve: Option<String>,
+
+    /// The address where to serve the decoded frames as JSON.
+    serve_json: Option<String>,
+
+    /// The address where to serve the decoded data over WebSocket.
+    #[cfg(feature = "websocket")]
+    websocket: Option<String>,
+
+    /// The address where to send the decoded data as OSC.
+    osc: Option<String>,
+
+    /// The prefix of the OSC addresses.
+    osc_prefix: Option<String>,
+
+    /// The sampling rate of the waveform sent as OSC, if any.
+    osc_waveform: Option<usize>,
+
+    /// The address of the MQTT broker where to publish the sleep state.
+    mqtt: Option<String>,
+
+    /// The prefix of the MQTT topics.
+    mqtt_topic: Option<String>,
+
+    /// Whether to publish the Home Assistant discovery payloads.
+    mqtt_discovery: bool,
+
+    /// The address where to serve the Prometheus metrics.
+    metrics: Option<String>,
+
+    /// The frequency of the mains noise to filter out of the waveform, if any.
+    mains_filter: Option<u32>,
+
+    /// The shell command to run at the start and end of each night.
+    hook: Option<String>,
+
+    /// Whether to re-publish the raw data on a new pseudo-terminal.
+    pty: bool,
+
+    /// The directory where to save the protocol explorer files.
+    explore: Option<String>,
+
+    /// Whether to run unattended, writing the file outputs per night.
+    daemon: bool,
+
+    /// The verbosity of the logs, relative to the default level.
+    verbosity: i8,
+}
+
+/// The path of the system configuration file.
+const SYSTEM_CONFIG: &str = "/etc/rawzeo/config.toml";
+
+impl Args {
+    /// Parses the command line arguments, over the configuration file
+    /// for the record command, exiting on error.
+    fn parse() -> Args {
+        let mut cli: Vec<String> = env::args().skip(1).collect();
+        // without a command, the arguments are those of the record one
+        let command = cli.first().and_then(|arg| Command::parse(arg));
+        if command.is_some() {
+            cli.remove(0);
+        }
+        let mut args = match command.unwrap_or_default() {
+            Command::Record => Args::load_config(&cli),
+            _ => Args::default(),
+        };
+        args.command = command.unwrap_or_default();
+        args.parse_cli(cli, command);
+        if let Some(hz) = args.mains_filter.filter(|hz| ![50, 60].contains(hz)) {
+            eprintln!("Invalid mains filter of {hz} Hz, it must be 50 or 60.");
+            ::std::process::exit(1);
+        }
+        if args.command.takes_input() && args.input.is_none() {
+            eprintln!("Missing the capture to {}.", args.command.name());
+            ::std::process::exit(1);
+        }
+        let mut exports = args.csv.is_some() || args.json || args.pcap.is_some();
+        exports |= args.edf.is_some() || args.explore.is_some();
+        #[cfg(feature = "sqlite")]
+        {
+            exports |= args.sqlite.is_some();
+        }
+        if args.command == Command::Export && !exports {
+            eprintln!("Missing the output to export to.");
+            ::std::process::exit(1);
+        }
+        args
+    }
+
+    /// Parses the command line `cli` arguments over the current ones,
+    /// given the explicit `command`, if any, exiting on error.
+    fn parse_cli(&mut self, cli: Vec<String>, command: Option<Command>) {
+        let mut iter = cli.into_iter();
+        while let Some(arg) = iter.next() {
+            if Command::Record.accepts(&arg) && !self.command.accepts(&arg) {
+                eprintln!(
+                    "The \"{arg}\" option doesn't apply to the {} command.",
+                    self.command.name()
+                );
+                ::std::process::exit(1);
+            }
+            match arg.as_str() {
+                "--config" => {
+                    iter.next();
+                }
+                "--csv" => self.csv = Some(Args::value(&arg, iter.next())),
+                "--edf" => self.edf = Some(Args::value(&arg, iter.next())),
+                "--json" => self.json = true,
+                "--no-json" => self.json = false,
+                #[cfg(feature = "sqlite")]
+                "--sqlite" => self.sqlite = Some(Args::value(&arg, iter.next())),
+                "--pcap" => self.pcap = Some(Args::value(&arg, iter.next())),
+                "--port" => self.port = Some(Args::value(&arg, iter.next())),
+                "--serial-number" => self.serial_number = Some(Args::value(&arg, iter.next())),
+                "--connect" => self.connect = Some(Args::value(&arg, iter.next())),
+                "--serve" => self.serve = Some(Args::value(&arg, iter.next())),
+                "--serve-json" => self.serve_json = Some(Args::value(&arg, iter.next())),
+                #[cfg(feature = "websocket")]
+                "--websocket" => self.websocket = Some(Args::value(&arg, iter.next())),
+                "--osc" => self.osc = Some(Args::value(&arg, iter.next())),
+                "--osc-prefix" => self.osc_prefix = Some(Args::value(&arg, iter.next())),
+                "--osc-waveform" => {
+                    let value = Args::value(&arg, iter.next());
+                    self.osc_waveform = Some(value.parse().unwrap_or_else(|_| {
+                        eprintln!("Invalid value \"{value}\" for \"{arg}\".");
+                        ::std::process::exit(1);
+                    }));
+                }
+                "--mqtt" => self.mqtt = Some(Args::value(&arg, iter.next())),
+                "--mqtt-topic" => self.mqtt_topic = Some(Args::value(&arg, iter.next())),
+                "--mqtt-discovery" => self.mqtt_discovery = true,
+                "--no-mqtt-discovery" => self.mqtt_discovery = false,
+                "--metrics" => self.metrics = Some(Args::value(&arg, iter.next())),
+                "--mains-filter" => {
+                    let value = Args::value(&arg, iter.next());
+                    self.mains_filter = Some(value.parse().unwrap_or_else(|_| {
+                        eprintln!("Invalid value \"{value}\" for \"{arg}\".");
+                        ::std::process::exit(1);
+                    }));
+                }
+                "--hook" => self.hook = Some(Args::value(&arg, iter.next())),
+                "--pty" => self.pty = true,
+                "--no-pty" => self.pty = false,
+                "--daemon" => self.daemon = true,
+                "--no-daemon" => self.daemon = false,
+                "--explore" => self.explore = Some(Args::value(&arg, iter.next())),
+                "-h" | "--help" => {
+                    match command {
+                        Some(command) => println!("{}", command.usage()),
+                        None => println!("{}", USAGE),
+                    }
+                    ::std::process::exit(0);
+                }
+                _ if Args::is_flags(&arg, 'v') => {
+                    self.verbosity = self.verbosity.saturating_add(Args::count_flags(&arg));
+                }
+                _ if Args::is_flags(&arg, 'q') => {
+                    self.verbosity = self.verbosity.saturating_sub(Args::count_flags(&arg));
+                }
+                _ if (arg == "-" || !arg.starts_with('-'))
+                    && self.command.takes_input()
+                    && self.input.is_none() =>
+                {
+                    self.input = Some(arg)
+                }
+                _ => {
+                    eprintln!("Unknown argument \"{arg}\".");
+                    ::std::process::exit(1);
+                }
+            }
+        }
+    }
+
+    /// Reads the capture given as the input, exiting on error.
+    fn read_input(&self) -> Vec<u8> {
+        let input = self.input.as_deref().unwrap_or("-");
+        dissect::read_input(input).unwrap_or_else(|e| {
+            error!("Failed to read \"{}\". Error: {}", input, e);
+            ::std::process::exit(1);
+        })
+    }
+
+    /// Creates the sink of a file output at `path`,
+    /// which in daemon mode is rotated per night.
+    fn output(&self, name: &'static str, path: &str, create: Create) -> io::Result<Box<dyn Sink>> {
+        if self.daemon {
+            Ok(Box::new(NightlySink::new(name, path, create)))
+        } else {
+            create(Path::new(path))
+        }
+    }
+
+    /// Loads the configuration file given with `--config`, or else the first
+    /// one found of the user and the system ones, exiting on error.
+    fn load_config(cli: &[String]) -> Args {
+        let path = match cli.iter().position(|arg| arg == "--config") {
+            Some(i) => PathBuf::from(Args::value("--config", cli.get(i + 1).cloned())),
+            None => match Args::config_paths().into_iter().find(|path| path.exists()) {
+                Some(path) => path,
+                None => return Args::default(),
+            },
+        };
+        let config = fs::read_to_string(&path).unwrap_or_else(|e| {
+            eprintln!("Failed to read \"{}\". Error: {}", path.display(), e);
+            ::std::process::exit(1);
+        });
+        let mut args: Args = toml::from_str(&config).unwrap_or_else(|e| {
+            eprintln!(
+                "Invalid configuration in \"{}\". Error: {}",
+                path.display(),
+                e
+            );
+            ::std::process::exit(1);
+        });
+        args.config = Some(path);
+        args
+    }
+
+    /// Returns the default paths of the configuration file, by priority.
+    fn config_paths() -> Vec<PathBuf> {
+        let user_dir = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+        let mut paths: Vec<_> = user_dir
+            .map(|dir| dir.join("rawzeo/config.toml"))
+            .into_iter()
+            .collect();
+        paths.push(SYSTEM_CONFIG.into());
+        paths
+    }
+
+    /// Returns whether the `arg` is a repetition of the short `flag`, e.g. `-vv`.
+    fn is_flags(arg: &str, flag: char) -> bool {
+        arg.len() > 1 && arg.starts_with('-') && arg[1..].chars().all(|c| c == flag)
+    }
+
+    /// Returns the number of repeated short flags in `arg`, saturated.
+    fn count_flags(arg: &str) -> i8 {
+        i8::try_from(arg.len() - 1).unwrap_or(i8::MAX)
+    }
+
+    /// Returns the maximum log level.
+    fn log_level(&self) -> LevelFilter {
+        match self.verbosity {
+            i8::MIN..=-2 => LevelFilter::Off,
+            -1 => LevelFilter::Error,
+            0 => LevelFilter::Info,
+            1 => LevelFilter::Debug,
+            2..=i8::MAX => LevelFilter::Trace,
+        }
+    }
+
+    /// Returns the value of an argument, exiting if it's missing.
+    fn value(arg: &str, value: Option<String>) -> String {
+        value.unwrap_or_else(|| {
+            eprintln!("Missing value for \"{arg}\".");
+            ::std::process::exit(1);
+        })
+    }
+}
+
+/// The commands.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum Command {
+    /// Captures the live data.
+    #[default]
+    Record,
+    /// Decodes a capture again, as if it was received live.
+    Replay,
+    /// Converts a capture into the file outputs.
+    Export,
+    /// Summarizes the night of a capture.
+    Report,
+    /// Dissects the frames of a capture.
+    Inspect,
+    /// Lists the serial ports.
+    Ports,
+}
+
+impl Command {
+    /// Returns the command with the given `name`, if any.
+    fn parse(name: &str) -> Option<Command> {
+        Some(match name {
+            "record" => Command::Record,
+            "replay" => Command::Replay,
+            "export" => Command::Export,
+            "report" => Command::Report,
+            // the former name of the command
+            "inspect" | "dissect" => Command::Inspect,
+            "ports" => Command::Ports,
+            _ => return None,
+        })
+    }
+
+    /// Returns the name of the command.
+    fn name(self) -> &'static str {
+        match self {
+            Command::Record => "record",
+            Command::Replay => "replay",
+            Command::Export => "export",
+            Command::Report => "report",
+            Command::Inspect => "inspect",
+            Command::Ports => "ports",
+        }
+    }
+
+    /// Returns whether the command reads a capture.
+    fn takes_input(self) -> bool {
+        matches!(
+            self,
+            Command::Replay | Command::Export | Command::Report | Command::Inspect
+        )
+    }
+
+    /// Returns the groups of options of the command,
+    /// besides the common ones.
+    fn options(self) -> &'static [&'static Options] {
+        match self {
+            Command::Record => &[
+                &LIVE_OPTIONS,
+                &DECODING_OPTIONS,
+                &FILE_OPTIONS,
+                &NETWORK_OPTIONS,
+            ],
+            Command::Replay => &[&DECODING_OPTIONS, &FILE_OPTIONS, &NETWORK_OPTIONS],
+            Command::Export => &[&DECODING_OPTIONS, &FILE_OPTIONS],
+            Command::Report | Command::Inspect | Command::Ports => &[],
+        }
+    }
+
+    /// Returns whether the command accepts the option `arg`.
+    fn accepts(self, arg: &str) -> bool {
+        self.options().iter().any(|o| o.names.contains(&arg))
+    }
+
+    /// Returns the help message of the command.
+    fn usage(self) -> String {
+        let (about, usage) = match self {
+            Command::Record => (
+                "Capture the live data of the headband, from the serial port\n\
+                 or from a raw data server.",
+                "rawzeo [record] [OPTIONS]",
+            ),
+            Command::Replay => (
+                "Decode a capture again, printing and serving it as if it\n\
+                 was received live.",
+                "rawzeo replay [OPTIONS] <CAPTURE>",
+            ),
+            Command::Export => (
+                "Convert a capture into CSV, EDF, JSON, PCAP, SQLite or explorer files.",
+                "rawzeo export [OPTIONS] <CAPTURE>",
+            ),
+            Command::Report => (
+                "Summarize the night of a capture: the time in each sleep stage,\n\
+                 the total sleep time, the sleep onset latency, the awakenings,\n\
+                 the signal quality and the events.",
+                "rawzeo report [OPTIONS] <CAPTURE>",
+            ),
+            Command::Inspect => (
+                "Print each frame of a capture dissected field by field.",
+                "rawzeo inspect [OPTIONS] <CAPTURE>",
+            ),
+            Command::Ports => (
+                "List the serial ports, with the details of the USB ones.",
+                "rawzeo ports [OPTIONS]",
+            ),
+        };
+        let mut help = format!["{about}\n\nUsage: {usage}\n"];
+        if self.takes_input() {
+            help += &format!["\n{CAPTURE_ARGUMENT}\n"];
+        }
+        for options in self.options() {
+            help += &format!["\n{}\n", options.help];
+        }
+        help + "\n" + COMMON_OPTIONS
+    }
+}
+
+/// A group of command line options.
+struct Options {
+    /// The names of the options.
+    names: &'static [&'static str],
+    /// The help message of the options.
+    help: &'static str,
+}
+
+/// The help message.
+const USAGE: &str = "Read raw data from Zeo headband.
+
+Usage: rawzeo [COMMAND] [OPTIONS]
+
+Commands:
+  record           Capture the live data of the headband [default]
+  replay           Decode a capture again, as if it was received live
+  export           Convert a capture into CSV, EDF, JSON, PCAP, SQLite or explorer files
+  report           Summarize the night of a capture
+  inspect          Print each frame of a capture dissected field by field
+  ports            List the serial ports
+
+Run `rawzeo <COMMAND> --help` for the options of each command.";
+
+/// The help message of the capture argument.
+const CAPTURE_ARGUMENT: &str = "Arguments:
+  <CAPTURE>        A raw capture file, a PCAP file exported by rawzeo,
+                   a hex dump file, a hex string, or - for stdin";
+
+/// The options of the live data.
+const LIVE_OPTIONS: Options = Options {
+    names: &[
+        "--port",
+        "--serial-number",
+        "--connect",
+        "--serve",
+        "--pty",
+        "--no-pty",
+        "--metrics",
+        "--hook",
+        "--daemon",
+        "--no-daemon",
+        "--config",
+    ],
+    help: "Live options:
+  --port <PATH>    Read the raw data from the serial port at PATH
+                   [default: /dev/ttyUSB0]. It's reopened whenever it fails,
+                   e.g. if the device is unplugged
+  --serial-number <SN>
+                   Read from the USB serial port with the serial number SN,
+                   looking it up again whenever it's reopened
+  --connect <HOST:PORT>
+                   Read the raw data from a server, instead of the serial port
+  --serve <ADDR>   Serve the unmodified raw data to TCP clients on ADDR
+  --pty            Re-publish the unmodified raw data on a new pseudo-terminal
+  --metrics <ADDR> Serve the link and signal metrics for Prometheus
+                   on http://ADDR/metrics
+  --hook <COMMAND> Run the shell COMMAND at the start and end of each night,
+                   with RAWZEO_NIGHT set to start or end, RAWZEO_EVENT to
+                   the name of the event, and RAWZEO_TIME to its unix time
+  --daemon         Run unattended: write the file outputs of each night into
+                   a new directory named by its date, inserted before the
+                   last component of their paths, or replacing {night} in them.
+                   It notifies systemd when ready, and pings its watchdog
+  --config <FILE>  Read the options from the TOML configuration FILE, instead
+                   of ~/.config/rawzeo/config.toml or /etc/rawzeo/config.toml,
+                   using the names of the long options as keys, and `verbosity`
+                   for the relative log level. The command line options
+                   take precedence, and --no-json, --no-pty, --no-daemon
+                   and --no-mqtt-discovery turn off the flags set in it",
+};
+
+/// The options of the decoding.
+const DECODING_OPTIONS: Options = Options {
+    names: &["--mains-filter"],
+    help: "Decoding options:
+  --mains-filter <HZ>
+                   Filter out the mains noise of 50 or 60 HZ from the decoded
+                   waveform, delaying it by 25 samples. The raw data and
+                   the PCAP export are left unfiltered",
+};
+
+/// The options of the file outputs.
+const FILE_OPTIONS: Options = Options {
+    names: &[
+        "--csv",
+        "--edf",
+        "--json",
+        "--no-json",
+        "--pcap",
+        "--sqlite",
+        "--explore",
+    ],
+    help: "Output options:
+  --csv <DIR>      Export the decoded data as CSV tables into DIR
+  --edf <FILE>     Export the waveform as EDF+ into FILE, annotated with the
+                   sleep stages and the events
+  --json           Print each decoded message as a line of JSON
+  --pcap <FILE>    Export the frames and the skipped bytes as PCAP into FILE
+  --sqlite <FILE>  Store the sessions, slices, stages, events and compressed
+                   waveform in the SQLite database FILE, adding to any
+                   previous nights. It's always a single file, even
+                   with --daemon
+  --explore <DIR>  Save the distributions of the values seen in the frames,
+                   and the payloads that couldn't be decoded, into DIR",
+};
+
+/// The options of the network outputs.
+const NETWORK_OPTIONS: Options = Options {
+    names: &[
+        "--serve-json",
+        "--websocket",
+        "--osc",
+        "--osc-prefix",
+        "--osc-waveform",
+        "--mqtt",
+        "--mqtt-topic",
+        "--mqtt-discovery",
+        "--no-mqtt-discovery",
+    ],
+    help: "Network options:
+  --serve-json <ADDR>
+                   Serve each decoded message as a line of JSON on ADDR
+  --websocket <ADDR>
+                   Serve the decoded data over WebSocket on ADDR, along with
+                   a dashboard page. The clients can choose the topics and
+                   format with e.g. ws://ADDR/?topics=waveform,stage&format=binary
+                   (topics: waveform, bins, signal, stage, events;
+                   formats: json, binary)
+  --osc <HOST:PORT>
+                   Send the frequency bins, signal quality, sleep stage and
+                   events as OSC messages over UDP to HOST:PORT
+  --osc-prefix <PREFIX>
+                   Prefix the OSC addresses with PREFIX [default: /zeo]
+  --osc-waveform <HZ>
+                   Also send the waveform as OSC, downsampled to HZ,
+                   which must divide 128
+  --mqtt <[USER[:PASSWORD]@]HOST:PORT>
+                   Publish the sleep stage, signal quality and events
+                   to an MQTT broker
+  --mqtt-topic <PREFIX>
+                   Prefix the MQTT topics with PREFIX [default: rawzeo]
+  --mqtt-discovery Also publish the Home Assistant discovery payloads",
+};
+
+/// The help message of the options common to all the commands.
+const COMMON_OPTIONS: &str = "Options:
+  -v               Log more details (-vv for hex dumps)
+  -q               Log only errors (-qq for nothing)
+  -h, --help       Print this help";
+
+/// A logger that writes to the standard error.
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("[{}] {}", record.level(), record.args());
+        }
+    }
+    fn flush(&self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_merge() {
+        let config = "json = true\npty = true\ndaemon = true\nmqtt-discovery = true\n\
+            csv = \"csv\"\npcap = \"zeo.pcap\"\nverbosity = 1\n";
+        let config: Args = toml::from_str(config).unwrap();
+
+        let cli = [
+            "--no-json",
+            "--no-pty",
+            "--no-daemon",
+            "--pcap",
+            "new.pcap",
+            "-q",
+        ];
+        let mut args = config.clone();
+        args.parse_cli(cli.iter().map(|arg| arg.to_string()).collect(), None);
+        assert![!args.json && !args.pty && !args.daemon];
+        assert![args.mqtt_discovery];
+        assert_eq![args.csv.as_deref(), Some("csv")];
+        assert_eq![args.pcap.as_deref(), Some("new.pcap")];
+        assert_eq![args.verbosity, 0];
+
+        let mut args = Args::default();
+        let cli = [
+            "--json",
+            "--mqtt-discovery",
+            "--no-mqtt-discovery",
+            "--daemon",
+        ];
+        args.parse_cli(cli.iter().map(|arg| arg.to_string()).collect(), None);
+        assert![args.json && args.daemon];
+        assert![!args.mqtt_discovery && !args.pty];
+
+        // the verbosity saturates instead of overflowing
+        let mut args = Args::default();
+        let cli = [format!["-{}", "v".repeat(200)], "-v".to_string()];
+        args.parse_cli(cli.to_vec(), None);
+        assert_eq![args.verbosity, i8::MAX];
+        let cli = [format!["-{}", "q".repeat(300)], "-qq".to_string()];
+        let mut args = Args::default();
+        args.parse_cli(cli.to_vec(), None);
+        assert_eq![args.verbosity, i8::MIN];
+    }
+}
@@ -0,0 +1,358 @@
+// rawzeo::bin::metrics
+//
+//! The metrics of the session, and their Prometheus endpoint.
+//
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::{debug, info};
+use rawzeo::{impedance, Data, DataType, SleepStages, Stats, SLEEP_STAGE_SECS};
+
+use crate::sink::Record;
+
+/// The time to wait for the request of a client.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The state of the session, as seen by the metrics.
+#[derive(Clone, Debug)]
+pub struct State {
+    /// The time the session started.
+    pub started: Instant,
+
+    /// The number of frames decoded, by datatype.
+    pub frames: BTreeMap<u8, u64>,
+
+    /// The number of frames whose data failed to decode.
+    pub decode_errors: u64,
+
+    /// The statistics of the decoded byte stream.
+    pub stats: Stats,
+
+    /// The number of times the source was reopened.
+    pub reconnects: u64,
+
+    /// The last signal quality index.
+    pub sqi: Option<u32>,
+
+    /// The last impedance magnitude in range.
+    pub impedance: Option<f64>,
+
+    /// The number of impedance readings out of range.
+    pub invalid_impedances: u64,
+
+    /// The current sleep stage.
+    pub stage: Option<SleepStages>,
+
+    /// The number of sleep stages received, by stage.
+    pub stages: BTreeMap<u8, u64>,
+
+    /// The time the last frame was received.
+    pub last_frame: Option<Instant>,
+}
+
+/// The metrics of the session, shared between threads.
+#[derive(Clone, Debug)]
+pub struct Metrics {
+    state: Arc<Mutex<State>>,
+}
+
+impl Metrics {
+    /// Returns new empty metrics, starting now.
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State {
+                started: Instant::now(),
+                frames: BTreeMap::new(),
+                decode_errors: 0,
+                stats: Stats::default(),
+                reconnects: 0,
+                sqi: None,
+                impedance: None,
+                invalid_impedances: 0,
+                stage: None,
+                stages: BTreeMap::new(),
+                last_frame: None,
+            })),
+        }
+    }
+
+    /// Returns the current state.
+    pub fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Updates the metrics with a decoded `record`.
+    pub fn record(&self, record: &Record) {
+        let mut state = self.state();
+        *state.frames.entry(record.msg.datatype.into()).or_default() += 1;
+        state.last_frame = Some(Instant::now());
+        match &record.data {
+            Ok(Data::Sqi(sqi)) => state.sqi = Some(*sqi),
+            // an out of range reading keeps the last valid one
+            Ok(Data::Impedance(raw)) => match impedance(*raw) {
+                Some(impedance) => state.impedance = Some(impedance),
+                None => state.invalid_impedances += 1,
+            },
+            Ok(Data::SleepStage(stage)) => {
+                state.stage = Some(*stage);
+                *state.stages.entry((*stage).into()).or_default() += 1;
+            }
+            Ok(_) => (),
+            Err(_) => state.decode_errors += 1,
+        }
+    }
+
+    /// Updates the statistics of the decoded byte stream.
+    pub fn set_stats(&self, stats: Stats) {
+        self.state().stats = stats;
+    }
+
+    /// Counts a reopening of the source.
+    pub fn reconnected(&self) {
+        self.state().reconnects += 1;
+    }
+
+    /// Writes the metrics in the Prometheus text exposition format.
+    pub fn write_prometheus(&self, out: &mut String) -> std::fmt::Result {
+        let state = self.state().clone();
+        let stats = state.stats;
+
+        metric(
+            out,
+            "frames_total",
+            "counter",
+            "Frames decoded, by datatype.",
+        )?;
+        for (datatype, n) in &state.frames {
+            let datatype = DataType::from(*datatype);
+            writeln!(out, "rawzeo_frames_total{{datatype=\"{datatype}\"}} {n}")?;
+        }
+        for (name, help, value) in [
+            (
+                "decode_errors_total",
+                "Frames whose data failed to decode.",
+                state.decode_errors,
+            ),
+            (
+                "invalid_checksums_total",
+                "Frames with an invalid checksum.",
+                stats.invalid_checksums,
+            ),
+            (
+                "invalid_lengths_total",
+                "Frames with an invalid length.",
+                stats.invalid_lengths,
+            ),
+            (
+                "unsupported_protocols_total",
+                "Frames with an unsupported protocol version.",
+                stats.unsupported_protocols,
+            ),
+            (
+                "lost_sequences_total",
+                "Sequence numbers lost.",
+                stats.lost_sequences,
+            ),
+            (
+                "duplicate_sequences_total",
+                "Sequence numbers received twice in a row.",
+                stats.duplicate_sequences,
+            ),
+            (
+                "skipped_bytes_total",
+                "Bytes discarded while resynchronizing.",
+                stats.skipped_bytes,
+            ),
+            (
+                "overrun_bytes_total",
+                "Bytes dropped by the decoder.",
+                stats.overrun_bytes,
+            ),
+            (
+                "invalid_impedances_total",
+                "Impedance readings out of range.",
+                state.invalid_impedances,
+            ),
+            (
+                "reconnects_total",
+                "Times the serial port was reopened.",
+                state.reconnects,
+            ),
+        ] {
+            metric(out, name, "counter", help)?;
+            writeln!(out, "rawzeo_{name} {value}")?;
+        }
+
+        if let Some(sqi) = state.sqi {
+            metric(out, "sqi", "gauge", "The last signal quality index (0-30).")?;
+            writeln!(out, "rawzeo_sqi {sqi}")?;
+        }
+        if let Some(impedance) = state.impedance {
+            metric(
+                out,
+                "impedance",
+                "gauge",
+                "The last impedance magnitude in range.",
+            )?;
+            writeln!(out, "rawzeo_impedance {impedance}")?;
+        }
+        if let Some(stage) = state.stage {
+            metric(
+                out,
+                "sleep_stage",
+                "gauge",
+                "The current sleep stage (0 undefined, 1 awake, 2 REM, 3 light, 4 deep).",
+            )?;
+            writeln!(out, "rawzeo_sleep_stage {}", u8::from(stage))?;
+        }
+
+        // before the first frame, the time since the session started
+        let since = state.last_frame.unwrap_or(state.started).elapsed();
+        metric(
+            out,
+            "seconds_since_last_frame",
+            "gauge",
+            "Seconds since the last frame was received.",
+        )?;
+        writeln!(
+            out,
+            "rawzeo_seconds_since_last_frame {:.3}",
+            since.as_secs_f64()
+        )
+    }
+
+    /// Writes a human readable summary of the session.
+    pub fn write_summary(&self, out: &mut String) -> std::fmt::Result {
+        let state = self.state().clone();
+        let stats = state.stats;
+
+        writeln!(
+            out,
+            "Session duration: {}",
+            hms(state.started.elapsed().as_secs())
+        )?;
+        let total: u64 = state.frames.values().sum();
+        write!(out, "Frames: {total}")?;
+        for (i, (datatype, n)) in state.frames.iter().enumerate() {
+            let sep = if i == 0 { " (" } else { ", " };
+            write!(out, "{sep}{} {n}", DataType::from(*datatype))?;
+        }
+        writeln!(out, "{}", if state.frames.is_empty() { "" } else { ")" })?;
+        writeln!(
+            out,
+            "Link: {} bytes skipped, {} invalid checksums, {} invalid lengths, \
+             {} unsupported protocols, {} lost sequences, {} duplicate sequences, \
+             {} bytes overrun, {} reconnects",
+            stats.skipped_bytes,
+            stats.invalid_checksums,
+            stats.invalid_lengths,
+            stats.unsupported_protocols,
+            stats.lost_sequences,
+            stats.duplicate_sequences,
+            stats.overrun_bytes,
+            state.reconnects,
+        )?;
+        write!(out, "Stages:")?;
+        if state.stages.is_empty() {
+            write!(out, " none")?;
+        }
+        let stages: u64 = state.stages.values().sum();
+        for (stage, n) in &state.stages {
+            let secs = n * SLEEP_STAGE_SECS as u64;
+            let percent = *n as f64 * 100. / stages as f64;
+            write!(
+                out,
+                " {} {} ({percent:.0}%)",
+                SleepStages::from(*stage),
+                hms(secs)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Formats a number of seconds as `H:MM:SS`.
+pub fn hms(secs: u64) -> String {
+    format!["{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60]
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Writes the help and type lines of a metric.
+fn metric(out: &mut String, name: &str, kind: &str, help: &str) -> std::fmt::Result {
+    writeln!(out, "# HELP rawzeo_{name} {help}")?;
+    writeln!(out, "# TYPE rawzeo_{name} {kind}")
+}
+
+/// Listens on `addr` and spawns the thread serving the `metrics` on `/metrics`.
+pub fn serve<A: ToSocketAddrs>(addr: A, metrics: Metrics) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    info!(
+        "Serving the metrics on http://{}/metrics",
+        listener.local_addr()?
+    );
+    thread::Builder::new()
+        .name("metrics".into())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                let result = stream.and_then(|stream| respond(stream, &metrics));
+                if let Err(e) = result {
+                    debug!("Failed to serve the metrics. Error: {}", e);
+                }
+            }
+        })?;
+    Ok(())
+}
+
+/// Responds to an HTTP request with the metrics.
+fn respond(mut stream: TcpStream, metrics: &Metrics) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut buf = [0; 1024];
+    let n = stream.read(&mut buf)?;
+    let found = buf[..n].starts_with(b"GET /metrics ") || buf[..n].starts_with(b"GET /metrics?");
+    let (status, body) = if found {
+        let mut body = String::new();
+        // writing to a string can't fail
+        let _ = metrics.write_prometheus(&mut body);
+        ("200 OK", body)
+    } else {
+        ("404 Not Found", "Not Found".to_string())
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_impedance() {
+        let metrics = Metrics::new();
+        // 3 and 4 from the middle of the range
+        metrics.record(&Record::with_data(100, Data::Impedance(0x8004_8003)));
+        metrics.record(&Record::with_data(101, Data::Impedance(0x8000_FFFF)));
+
+        let mut out = String::new();
+        metrics.write_prometheus(&mut out).unwrap();
+        let lines: Vec<_> = out.lines().collect();
+        assert![lines.contains(&"rawzeo_impedance 5")];
+        assert![lines.contains(&"rawzeo_invalid_impedances_total 1")];
+    }
+}
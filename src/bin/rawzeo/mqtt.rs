@@ -0,0 +1,557 @@
+// rawzeo::bin::mqtt
+//
+//! The MQTT publisher, for home automation.
+//!
+//! It implements just the subset of MQTT 3.1.1 needed to publish at QoS 0:
+//! CONNECT, PUBLISH, PINGREQ and DISCONNECT, reading back only CONNACK and
+//! PINGRESP. The client crates are built on an async runtime, or spawn their
+//! own event loop, which is a lot to depend on for the sync binary in order
+//! to send a few small messages per minute.
+//
+
+use std::{
+    io::{self, Read, Write},
+    net::{Shutdown, TcpStream, ToSocketAddrs},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::{debug, info, warn};
+use rawzeo::{Data, SleepStages};
+use serde_json::json;
+
+use crate::sink::{Record, Sink};
+
+/// The default prefix of the MQTT topics.
+pub const MQTT_TOPIC: &str = "rawzeo";
+
+/// The prefix of the Home Assistant discovery topics.
+const DISCOVERY_PREFIX: &str = "homeassistant";
+
+/// The keep alive interval, in seconds.
+const KEEP_ALIVE: u16 = 60;
+
+/// The timeout of connecting to the broker, and of writing to it.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The initial delay before reconnecting to the broker.
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The maximum delay before reconnecting to the broker.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Publishes the sleep state to an MQTT broker (MQTT 3.1.1, QoS 0):
+///
+/// | topic            | payload                         | retained |
+/// |------------------|---------------------------------|----------|
+/// | `rawzeo/status`  | `online`, or `offline`          | yes      |
+/// | `rawzeo/stage`   | the sleep stage, on changes     | yes      |
+/// | `rawzeo/sqi`     | the signal quality index        | yes      |
+/// | `rawzeo/event`   | the name of each event          | no       |
+///
+/// Optionally it also publishes the Home Assistant discovery payloads,
+/// so that the topics appear there as the sensors of a Zeo device.
+///
+/// If the connection is lost it reconnects with backoff, publishing again
+/// the status and the retained stage. The messages published meanwhile
+/// are dropped.
+pub struct MqttSink {
+    host: String,
+    credentials: Option<(String, String)>,
+    topic: String,
+    discovery: bool,
+    // the connection, shared with its keep alive thread, if it's open
+    stream: Option<Arc<Mutex<TcpStream>>>,
+    // when to try reconnecting next, and the delay after that
+    retry_at: Instant,
+    backoff: Duration,
+    // the number of messages dropped while disconnected
+    dropped: u64,
+    stage: Option<SleepStages>,
+}
+
+impl MqttSink {
+    /// Connects to the broker at `addr`, given as `[USER[:PASSWORD]@]HOST:PORT`,
+    /// and spawns the thread keeping the connection alive.
+    ///
+    /// The topics are prefixed with `topic`.
+    pub fn connect(addr: &str, topic: &str, discovery: bool) -> io::Result<Self> {
+        let (credentials, host) = match addr.rsplit_once('@') {
+            Some((credentials, host)) => (
+                Some(credentials.split_once(':').unwrap_or((credentials, ""))),
+                host,
+            ),
+            None => (None, addr),
+        };
+        let mut sink = Self {
+            host: host.to_string(),
+            credentials: credentials.map(|(u, p)| (u.to_string(), p.to_string())),
+            topic: topic.trim_end_matches('/').to_string(),
+            discovery,
+            stream: None,
+            retry_at: Instant::now(),
+            backoff: MIN_BACKOFF,
+            dropped: 0,
+            stage: None,
+        };
+        sink.open()?;
+        info!(
+            "Publishing to the MQTT broker at {} under \"{}\"",
+            sink.host, sink.topic
+        );
+        Ok(sink)
+    }
+
+    /// Opens the connection and spawns the thread keeping it alive,
+    /// and then publishes the discovery payloads, the status and the stage.
+    fn open(&mut self) -> io::Result<()> {
+        let mut stream = connect_stream(&self.host)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+
+        let mut packet = vec![];
+        let credentials = self.credentials.as_ref();
+        let credentials = credentials.map(|(u, p)| (u.as_str(), p.as_str()));
+        connect_packet(&mut packet, &self.topic, credentials);
+        stream.write_all(&packet)?;
+        let mut connack = [0; 4];
+        stream.read_exact(&mut connack)?;
+        match connack {
+            [0x20, 2, _, 0] => (),
+            [0x20, 2, _, code] => {
+                return Err(io::Error::new(
+                    io::ErrorKind::ConnectionRefused,
+                    format!["The broker refused the connection (code {code})."],
+                ))
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "The broker didn't acknowledge the connection.",
+                ))
+            }
+        }
+
+        let reading = stream.try_clone()?;
+        let stream = Arc::new(Mutex::new(stream));
+        let pinging = stream.clone();
+        let interval = Duration::from_secs(KEEP_ALIVE as u64 / 2);
+        thread::Builder::new()
+            .name("mqtt".into())
+            .spawn(move || keep_alive(reading, pinging, interval))?;
+        self.stream = Some(stream);
+
+        if self.discovery {
+            self.publish_discovery()?;
+        }
+        self.send(&format!["{}/status", self.topic], b"online", true)?;
+        if let Some(stage) = self.stage {
+            let topic = format!["{}/stage", self.topic];
+            self.send(&topic, stage.to_string().as_bytes(), true)?;
+        }
+        Ok(())
+    }
+
+    /// Closes the connection, if it's open.
+    fn close(&mut self) {
+        if let Some(stream) = self.stream.take() {
+            let stream = stream.lock().unwrap_or_else(|e| e.into_inner());
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+
+    /// Reconnects to the broker, unless it's too soon after the last attempt.
+    ///
+    /// Returns whether it's connected.
+    fn reconnect(&mut self) -> bool {
+        if Instant::now() < self.retry_at {
+            return false;
+        }
+        match self.open() {
+            Ok(()) => {
+                info!(
+                    "Reconnected to the MQTT broker at {}, after dropping {} messages.",
+                    self.host, self.dropped
+                );
+                self.backoff = MIN_BACKOFF;
+                self.dropped = 0;
+                true
+            }
+            Err(e) => {
+                self.close();
+                warn!(
+                    "Failed to reconnect to the MQTT broker, retrying in {:?}. Error: {}",
+                    self.backoff, e
+                );
+                self.retry_at = Instant::now() + self.backoff;
+                self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+                false
+            }
+        }
+    }
+
+    /// Publishes the `payload` to the prefixed `topic`.
+    fn publish(&mut self, topic: &str, payload: &[u8], retain: bool) {
+        self.publish_to(&format!["{}/{topic}", self.topic], payload, retain)
+    }
+
+    /// Publishes the `payload` to the unprefixed `topic`, reconnecting
+    /// if needed, or else dropping it.
+    fn publish_to(&mut self, topic: &str, payload: &[u8], retain: bool) {
+        // the connection may turn out to be lost only when writing to it
+        for _ in 0..2 {
+            if self.stream.is_none() && !self.reconnect() {
+                break;
+            }
+            match self.send(topic, payload, retain) {
+                Ok(()) => return,
+                Err(e) => {
+                    warn!("Lost the connection to the MQTT broker. Error: {}", e);
+                    self.close();
+                }
+            }
+        }
+        self.dropped += 1;
+    }
+
+    /// Sends the PUBLISH packet of the `payload` to the unprefixed `topic`.
+    fn send(&self, topic: &str, payload: &[u8], retain: bool) -> io::Result<()> {
+        let stream = match &self.stream {
+            Some(stream) => stream,
+            None => return Err(io::ErrorKind::NotConnected.into()),
+        };
+        let mut body = vec![];
+        push_str(&mut body, topic);
+        body.extend(payload);
+        let mut packet = vec![];
+        push_packet(&mut packet, 0x30 | retain as u8, &body);
+        stream
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .write_all(&packet)
+    }
+
+    /// Publishes the retained Home Assistant discovery payloads.
+    fn publish_discovery(&self) -> io::Result<()> {
+        let node = self.topic.replace('/', "_");
+        for (object, name) in [
+            ("stage", "Sleep stage"),
+            ("sqi", "Signal quality"),
+            ("event", "Event"),
+        ] {
+            let config = json!({
+                "name": name,
+                "unique_id": format!["{node}_{object}"],
+                "state_topic": format!["{}/{object}", self.topic],
+                "availability_topic": format!["{}/status", self.topic],
+                "device": {
+                    "identifiers": [node],
+                    "name": "Zeo",
+                    "manufacturer": "Zeo, Inc.",
+                },
+            });
+            let topic = format!["{DISCOVERY_PREFIX}/sensor/{node}/{object}/config"];
+            self.send(&topic, config.to_string().as_bytes(), true)?;
+        }
+        Ok(())
+    }
+}
+
+impl Sink for MqttSink {
+    fn name(&self) -> &str {
+        "mqtt"
+    }
+    fn write(&mut self, record: &Record) -> io::Result<()> {
+        match &record.data {
+            Ok(Data::SleepStage(stage)) if self.stage != Some(*stage) => {
+                self.stage = Some(*stage);
+                self.publish("stage", stage.to_string().as_bytes(), true);
+            }
+            Ok(Data::Sqi(sqi)) => self.publish("sqi", sqi.to_string().as_bytes(), true),
+            Ok(Data::Event(event)) => self.publish("event", event.to_string().as_bytes(), false),
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+impl Drop for MqttSink {
+    fn drop(&mut self) {
+        let _ = self.send(&format!["{}/status", self.topic], b"offline", true);
+        if let Some(stream) = &self.stream {
+            // DISCONNECT
+            let mut stream = stream.lock().unwrap_or_else(|e| e.into_inner());
+            let _ = stream.write_all(&[0xE0, 0]);
+        }
+        self.close();
+    }
+}
+
+/// Connects to the first address of `host` that accepts the connection.
+fn connect_stream(host: &str) -> io::Result<TcpStream> {
+    let mut error = io::Error::new(io::ErrorKind::InvalidInput, "No address to connect to.");
+    for addr in host.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => error = e,
+        }
+    }
+    Err(error)
+}
+
+/// Pings the broker whenever it has been quiet for the `interval`,
+/// which is half the keep alive one, until the connection is closed,
+/// or the broker doesn't respond to a ping within the `interval`.
+///
+/// Then it shuts the connection down, so that the next write fails
+/// and the sink reconnects.
+fn keep_alive(reading: TcpStream, stream: Arc<Mutex<TcpStream>>, interval: Duration) {
+    keep_pinging(&reading, &stream, interval);
+    let _ = reading.shutdown(Shutdown::Both);
+}
+
+/// The loop of [`keep_alive`].
+fn keep_pinging(mut reading: &TcpStream, stream: &Mutex<TcpStream>, interval: Duration) {
+    if let Err(e) = reading.set_read_timeout(Some(interval)) {
+        return warn!("Failed to set the MQTT read timeout. Error: {}", e);
+    }
+    let mut buf = [0; 64];
+    let mut pinged = false;
+    loop {
+        match reading.read(&mut buf) {
+            Ok(0) => return info!("The MQTT connection was closed."),
+            // the only packets expected are the ping responses
+            Ok(_) => pinged = false,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                if pinged {
+                    return warn!("The MQTT broker didn't respond to the ping.");
+                }
+                pinged = true;
+                // PINGREQ
+                let ping = stream
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .write_all(&[0xC0, 0]);
+                if let Err(e) = ping {
+                    return warn!("Failed to ping the MQTT broker. Error: {}", e);
+                }
+            }
+            Err(e) => return debug!("Failed to read from the MQTT broker. Error: {}", e),
+        }
+    }
+}
+
+/// Appends a CONNECT packet, with the status `topic` as the retained `offline`
+/// last will, and the optional user name and password.
+fn connect_packet(buf: &mut Vec<u8>, topic: &str, credentials: Option<(&str, &str)>) {
+    let mut body = vec![];
+    push_str(&mut body, "MQTT");
+    // protocol level 4 is 3.1.1
+    body.push(4);
+    // clean session, will with retain
+    let mut flags = 0x02 | 0x04 | 0x20;
+    if let Some((_, password)) = credentials {
+        flags |= if password.is_empty() { 0x80 } else { 0xC0 };
+    }
+    body.push(flags);
+    body.extend(KEEP_ALIVE.to_be_bytes());
+    push_str(&mut body, &format!["rawzeo-{}", std::process::id()]);
+    push_str(&mut body, &format!["{topic}/status"]);
+    push_str(&mut body, "offline");
+    if let Some((user, password)) = credentials {
+        push_str(&mut body, user);
+        if !password.is_empty() {
+            push_str(&mut body, password);
+        }
+    }
+    push_packet(buf, 0x10, &body);
+}
+
+/// Appends a packet: its first byte, the remaining length, and the `body`.
+fn push_packet(buf: &mut Vec<u8>, first: u8, body: &[u8]) {
+    buf.push(first);
+    let mut len = body.len();
+    loop {
+        let byte = (len % 128) as u8;
+        len /= 128;
+        if len == 0 {
+            buf.push(byte);
+            break;
+        }
+        buf.push(byte | 0x80);
+    }
+    buf.extend(body);
+}
+
+/// Appends an MQTT string: its length as a big endian `u16`, and its bytes.
+fn push_str(buf: &mut Vec<u8>, s: &str) {
+    buf.extend((s.len() as u16).to_be_bytes());
+    buf.extend(s.as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{net::TcpListener, sync::mpsc};
+
+    /// Reads a packet, returning its first byte and its body.
+    fn read_packet(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+        let mut byte = [0];
+        stream.read_exact(&mut byte).unwrap();
+        let first = byte[0];
+        let (mut len, mut shift) = (0, 0);
+        loop {
+            stream.read_exact(&mut byte).unwrap();
+            len |= (byte[0] as usize & 0x7F) << shift;
+            shift += 7;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = vec![0; len];
+        stream.read_exact(&mut body).unwrap();
+        (first, body)
+    }
+
+    /// Returns the body of a PUBLISH packet.
+    fn publish(topic: &str, payload: &str) -> Vec<u8> {
+        let mut body = vec![];
+        push_str(&mut body, topic);
+        body.extend(payload.as_bytes());
+        body
+    }
+
+    /// Accepts a connection, checking its CONNECT packet,
+    /// and returns it along with the packets following the acknowledgement.
+    fn accept(listener: &TcpListener, packets: usize) -> (TcpStream, Vec<(u8, Vec<u8>)>) {
+        let (mut stream, _) = listener.accept().unwrap();
+        let (first, body) = read_packet(&mut stream);
+        assert_eq![first, 0x10];
+        let mut expected = vec![0, 4];
+        expected.extend(b"MQTT\x04\xE6\x00\x3C");
+        push_str(&mut expected, &format!["rawzeo-{}", std::process::id()]);
+        for s in ["zeo/status", "offline", "user", "secret"] {
+            push_str(&mut expected, s);
+        }
+        assert_eq![body, expected];
+        stream.write_all(&[0x20, 2, 0, 0]).unwrap();
+        let packets = (0..packets).map(|_| read_packet(&mut stream)).collect();
+        (stream, packets)
+    }
+
+    #[test]
+    fn remaining_length() {
+        for (len, encoded) in [
+            (0, &[0x00][..]),
+            (127, &[0x7F]),
+            (128, &[0x80, 0x01]),
+            (16_383, &[0xFF, 0x7F]),
+            (16_384, &[0x80, 0x80, 0x01]),
+            (2_097_152, &[0x80, 0x80, 0x80, 0x01]),
+        ] {
+            let mut buf = vec![];
+            push_packet(&mut buf, 0x30, &vec![7; len]);
+            assert_eq![buf[0], 0x30];
+            assert_eq![&buf[1..1 + encoded.len()], encoded];
+            assert_eq![buf.len(), 1 + encoded.len() + len];
+        }
+    }
+
+    #[test]
+    fn connack() {
+        for (connack, kind) in [
+            ([0x20, 2, 0, 5], io::ErrorKind::ConnectionRefused),
+            ([0x90, 3, 0, 1], io::ErrorKind::InvalidData),
+        ] {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap().to_string();
+            let broker = thread::spawn(move || {
+                let (mut stream, _) = listener.accept().unwrap();
+                assert_eq![read_packet(&mut stream).0, 0x10];
+                stream.write_all(&connack).unwrap();
+                // until the client closes the connection
+                let _ = stream.read(&mut [0]);
+            });
+            let error = MqttSink::connect(&addr, "zeo", false).err().unwrap();
+            assert_eq![error.kind(), kind];
+            broker.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn pingresp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = TcpStream::connect(addr).unwrap();
+        let (mut broker, _) = listener.accept().unwrap();
+        let reading = client.try_clone().unwrap();
+        let stream = Arc::new(Mutex::new(client));
+        let interval = Duration::from_millis(50);
+        let pinging = thread::spawn(move || keep_alive(reading, stream, interval));
+
+        // each ping is answered, so the client keeps pinging
+        for _ in 0..3 {
+            assert_eq![read_packet(&mut broker), (0xC0, vec![])];
+            broker.write_all(&[0xD0, 0]).unwrap();
+        }
+        // until a ping isn't answered, and the client shuts the connection down
+        assert_eq![read_packet(&mut broker), (0xC0, vec![])];
+        pinging.join().unwrap();
+        assert_eq![broker.read(&mut [0]).unwrap(), 0];
+    }
+
+    #[test]
+    fn publish_and_reconnect() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = format!["user:secret@{}", listener.local_addr().unwrap()];
+        let (reconnected, has_reconnected) = mpsc::channel();
+        let broker = thread::spawn(move || {
+            let (stream, first) = accept(&listener, 3);
+            // the connection is lost, and the sink reconnects
+            drop(stream);
+            let (mut stream, second) = accept(&listener, 2);
+            reconnected.send(()).unwrap();
+            let mut last = read_packet(&mut stream);
+            // skip the messages sent while reconnecting
+            while last.0 != 0xE0 {
+                last = read_packet(&mut stream);
+            }
+            (first, second)
+        });
+
+        let mut sink = MqttSink::connect(&addr, "zeo/", false).unwrap();
+        let stage = Record::with_data(0, Data::SleepStage(SleepStages::Light));
+        let sqi = Record::with_data(0, Data::Sqi(25));
+        sink.write(&stage).unwrap();
+        sink.write(&sqi).unwrap();
+        // until the broker has accepted the new connection
+        while has_reconnected.try_recv().is_err() {
+            sink.write(&sqi).unwrap();
+            thread::sleep(Duration::from_millis(10));
+        }
+        drop(sink);
+
+        let (first, second) = broker.join().unwrap();
+        assert_eq![
+            first,
+            [
+                (0x31, publish("zeo/status", "online")),
+                (0x31, publish("zeo/stage", "Light")),
+                (0x31, publish("zeo/sqi", "25")),
+            ]
+        ];
+        assert_eq![
+            second,
+            [
+                (0x31, publish("zeo/status", "online")),
+                (0x31, publish("zeo/stage", "Light")),
+            ]
+        ];
+    }
+}
@@ -0,0 +1,280 @@
+// rawzeo::bin::nightly
+//
+//! The rotation of the file outputs per night.
+//
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use log::{error, info};
+use rawzeo::{Data, EventType};
+
+use crate::sink::{Record, Sink, Skipped};
+
+/// The placeholder of the name of the night in the output paths.
+pub const NIGHT: &str = "{night}";
+
+/// Creates a sink writing to the given path.
+pub type Create = fn(&Path) -> io::Result<Box<dyn Sink>>;
+
+/// Writes each night to a new sink, created at a path named after its date.
+///
+/// A night starts with a `NightStart` or `HeadbandUnDocked` event, and ends
+/// with a `NightEnd` or `HeadbandDocked` event. The records in between nights
+/// are discarded, except before the first event, when the night is assumed
+/// to be already in progress.
+///
+/// The path of each night is the `pattern` with [`NIGHT`] replaced by its
+/// name, e.g. `2023-02-01`, followed by `-2`, `-3`… if it already exists.
+/// Without the placeholder, the name is inserted as the parent directory
+/// of the last component, e.g. `out/zeo.pcap` becomes `out/2023-02-01/zeo.pcap`.
+pub struct NightlySink {
+    name: &'static str,
+    pattern: String,
+    create: Create,
+    sink: Option<Box<dyn Sink>>,
+    // whether the last night has ended
+    ended: bool,
+}
+
+impl NightlySink {
+    /// Returns a new sink named `name`, that will `create` a sink
+    /// at the path of each night following the `pattern`.
+    pub fn new(name: &'static str, pattern: &str, create: Create) -> Self {
+        Self {
+            name,
+            pattern: pattern.to_string(),
+            create,
+            sink: None,
+            ended: false,
+        }
+    }
+
+    /// Starts a new night with the given first `record`.
+    fn start(&mut self, record: &Record) -> io::Result<()> {
+        // on failure, wait for the next night instead of retrying on each record
+        self.ended = true;
+        let path = self.night_path(record);
+        info!(
+            "Starting a new night of {} in \"{}\"",
+            self.name,
+            path.display()
+        );
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        self.sink = Some((self.create)(&path)?);
+        self.ended = false;
+        Ok(())
+    }
+
+    /// Ends the current night, if any, closing its sink.
+    fn end(&mut self) {
+        if let Some(mut sink) = self.sink.take() {
+            if let Err(e) = sink.flush() {
+                error!("Failed to flush the {} sink. Error: {}", self.name, e);
+            }
+            info!("The night of {} has ended.", self.name);
+        }
+        self.ended = true;
+    }
+
+    /// Returns the first path that doesn't exist yet for the night
+    /// starting with the given `record`.
+    fn night_path(&self, record: &Record) -> PathBuf {
+        // the clock of the headband is set to the local time
+        let secs = match record.msg.time {
+            0 => record
+                .received
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            time => time as u64,
+        };
+        let date = date(secs);
+        let mut n = 1;
+        loop {
+            let night = match n {
+                1 => date.clone(),
+                n => format!["{date}-{n}"],
+            };
+            let path = night_path(&self.pattern, &night);
+            if !path.exists() {
+                return path;
+            }
+            n += 1;
+        }
+    }
+}
+
+impl Sink for NightlySink {
+    fn name(&self) -> &str {
+        self.name
+    }
+    fn write(&mut self, record: &Record) -> io::Result<()> {
+        use EventType::*;
+        match &record.data {
+            Ok(Data::Event(NightStart | HeadbandUnDocked)) if self.sink.is_none() => {
+                self.start(record)?
+            }
+            Ok(Data::Event(NightEnd | HeadbandDocked)) => {
+                if let Some(sink) = &mut self.sink {
+                    sink.write(record)?;
+                }
+                self.end();
+                return Ok(());
+            }
+            _ if self.sink.is_none() && !self.ended => self.start(record)?,
+            _ => (),
+        }
+        match &mut self.sink {
+            Some(sink) => sink.write(record),
+            None => Ok(()),
+        }
+    }
+    fn write_skipped(&mut self, skipped: &Skipped) -> io::Result<()> {
+        match &mut self.sink {
+            Some(sink) => sink.write_skipped(skipped),
+            None => Ok(()),
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.sink {
+            Some(sink) => sink.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Returns the path following the `pattern` for the given `night`.
+fn night_path(pattern: &str, night: &str) -> PathBuf {
+    if pattern.contains(NIGHT) {
+        return pattern.replace(NIGHT, night).into();
+    }
+    let path = Path::new(pattern);
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => parent.join(night).join(name),
+        _ => Path::new(night).join(path),
+    }
+}
+
+/// Returns the date of a number of seconds since the epoch, as `YYYY-MM-DD`.
+pub fn date(secs: u64) -> String {
+    // the civil date from the days since the epoch, in the proleptic
+    // gregorian calendar, as eras of 400 years starting on March 1st
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!["{year:04}-{month:02}-{day:02}"]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rawzeo::{DataType, SleepStages};
+    use std::{fs::File, io::Write};
+
+    /// A sink writing the time of each record to a file.
+    struct TimesSink(File);
+
+    impl Sink for TimesSink {
+        fn name(&self) -> &str {
+            "times"
+        }
+        fn write(&mut self, record: &Record) -> io::Result<()> {
+            writeln!(self.0, "{}", record.msg.time)
+        }
+    }
+
+    fn create(path: &Path) -> io::Result<Box<dyn Sink>> {
+        Ok(Box::new(TimesSink(File::create(path)?)))
+    }
+
+    /// Returns a record of the event decoded from its wire `code`.
+    fn event(time: u32, code: u8) -> Record {
+        let data = Data::decode(DataType::Event, &[code, 0, 0, 0]).unwrap();
+        Record::with_data(time, data)
+    }
+
+    #[test]
+    fn rotation() {
+        let dir = std::env::temp_dir().join(format!["rawzeo-nightly-{}", std::process::id()]);
+        let _ = fs::remove_dir_all(&dir);
+        let pattern = dir.join("{night}.txt");
+        let mut sink = NightlySink::new("times", pattern.to_str().unwrap(), create);
+        let stage = |time| Record::with_data(time, Data::SleepStage(SleepStages::Light));
+
+        // 2023-02-01 22:00, a night already in progress
+        let t = 1_675_288_800;
+        for record in [
+            stage(t),
+            stage(t + 30),
+            event(t + 60, 0x15),  // night end
+            stage(t + 90),        // discarded
+            event(t + 120, 0x0F), // undocked, the same date
+            stage(t + 150),
+            event(t + 180, 0x0E),      // docked
+            stage(t + 210),            // discarded
+            event(t + 3 * 3600, 0x05), // night start, the next date
+            stage(t + 3 * 3600 + 30),
+        ] {
+            sink.write(&record).unwrap();
+        }
+        sink.flush().unwrap();
+
+        let times = |name: &str| fs::read_to_string(dir.join(name)).unwrap();
+        assert_eq![
+            times("2023-02-01.txt"),
+            format!["{}\n{}\n{}\n", t, t + 30, t + 60]
+        ];
+        assert_eq![
+            times("2023-02-01-2.txt"),
+            format!["{}\n{}\n{}\n", t + 120, t + 150, t + 180]
+        ];
+        assert_eq![
+            times("2023-02-02.txt"),
+            format!["{}\n{}\n", t + 3 * 3600, t + 3 * 3600 + 30]
+        ];
+        assert_eq![fs::read_dir(&dir).unwrap().count(), 3];
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn paths() {
+        assert_eq![
+            night_path("out/{night}/zeo.pcap", "2023-02-01"),
+            Path::new("out/2023-02-01/zeo.pcap")
+        ];
+        assert_eq![
+            night_path("out/zeo-{night}.pcap", "2023-02-01"),
+            Path::new("out/zeo-2023-02-01.pcap")
+        ];
+        assert_eq![
+            night_path("out/csv", "2023-02-01"),
+            Path::new("out/2023-02-01/csv")
+        ];
+        assert_eq![
+            night_path("zeo.pcap", "2023-02-01"),
+            Path::new("2023-02-01/zeo.pcap")
+        ];
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq![date(0), "1970-01-01"];
+        assert_eq![date(951_782_400), "2000-02-29"];
+        assert_eq![date(1_675_288_800), "2023-02-01"];
+        assert_eq![date(1_675_299_600), "2023-02-02"];
+        assert_eq![date(4_107_542_400), "2100-03-01"];
+        assert_eq![date(253_402_300_799), "9999-12-31"];
+    }
+}
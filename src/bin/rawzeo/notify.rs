@@ -0,0 +1,70 @@
+// rawzeo::bin::notify
+//
+//! The notifications to the systemd service manager.
+//
+
+use std::{
+    env, io,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use log::{debug, warn};
+
+/// Sends a `state` notification to the service manager, e.g. `READY=1`,
+/// if the process was started by one with a notification socket.
+#[cfg(unix)]
+pub fn notify(state: &str) {
+    use std::os::unix::net::UnixDatagram;
+
+    let path = match env::var_os("NOTIFY_SOCKET") {
+        Some(path) => path,
+        None => return,
+    };
+    // abstract sockets (starting with @) aren't supported
+    let result = UnixDatagram::unbound().and_then(|s| s.send_to(state.as_bytes(), &path));
+    if let Err(e) = result {
+        debug!("Failed to notify the service manager. Error: {}", e);
+    }
+}
+
+/// Sends a `state` notification to the service manager (unsupported).
+#[cfg(not(unix))]
+pub fn notify(_state: &str) {}
+
+/// Returns the interval of the watchdog of the service manager,
+/// if it's enabled for this process.
+pub fn watchdog() -> Option<Duration> {
+    let usec: u64 = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse() != Ok(std::process::id()) {
+            return None;
+        }
+    }
+    Some(Duration::from_micros(usec))
+}
+
+/// Spawns the thread pinging the watchdog every half `interval`,
+/// as long as the `heartbeat` keeps changing.
+pub fn spawn_watchdog(interval: Duration, heartbeat: Arc<AtomicU64>) -> io::Result<()> {
+    thread::Builder::new()
+        .name("watchdog".into())
+        .spawn(move || {
+            let mut last = None;
+            loop {
+                let beat = heartbeat.load(Ordering::Relaxed);
+                if last != Some(beat) {
+                    notify("WATCHDOG=1");
+                } else {
+                    warn!("The reader seems to be stuck.");
+                }
+                last = Some(beat);
+                thread::sleep(interval / 2);
+            }
+        })?;
+    Ok(())
+}
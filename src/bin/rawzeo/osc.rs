@@ -0,0 +1,242 @@
+// rawzeo::bin::osc
+//
+//! The Open Sound Control output.
+//
+
+use std::{
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use log::warn;
+
+use rawzeo::{waveform_uv, Data, FrequencyBins, FREQUENCY_BINS_LEN, WAVEFORM_HZ};
+
+use crate::sink::{Record, Sink};
+
+/// The default prefix of the OSC addresses.
+pub const OSC_PREFIX: &str = "/zeo";
+
+/// The minimum interval between the warnings about refused messages.
+const WARN_INTERVAL: Duration = Duration::from_secs(60);
+
+/// An argument of an OSC message.
+enum Arg<'a> {
+    Int(i32),
+    Float(f32),
+    Str(&'a str),
+}
+
+/// Sends the decoded data as OSC messages over UDP:
+///
+/// | address                    | arguments                       |
+/// |----------------------------|---------------------------------|
+/// | `/zeo/bins/delta` … `gamma`| the raw power of the bin        |
+/// | `/zeo/sqi`                 | the signal quality index        |
+/// | `/zeo/stage`               | the sleep stage code and name   |
+/// | `/zeo/event`               | the event code and name         |
+/// | `/zeo/waveform`            | the downsampled µV samples      |
+///
+/// The waveform is only sent if a sampling rate is given.
+///
+/// The messages refused while nothing is listening at the destination
+/// are dropped and counted, with a warning at most once a minute.
+pub struct OscSink {
+    socket: UdpSocket,
+    addr: SocketAddr,
+    // the number of messages refused, and when it was last warned about
+    refused: u64,
+    warned: Option<Instant>,
+    prefix: String,
+    // the number of samples averaged into each downsampled one
+    waveform_step: Option<usize>,
+    buf: Vec<u8>,
+}
+
+impl OscSink {
+    /// Returns a new sink sending to `addr`, prefixing the OSC addresses
+    /// with `prefix`, and sending the waveform downsampled to `waveform_hz`.
+    pub fn new<A: ToSocketAddrs>(
+        addr: A,
+        prefix: &str,
+        waveform_hz: Option<usize>,
+    ) -> io::Result<Self> {
+        let addr = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No address to send to."))?;
+        // bind to the same address family as the destination
+        let socket = match addr {
+            SocketAddr::V4(_) => UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?,
+            SocketAddr::V6(_) => UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0))?,
+        };
+        socket.connect(addr)?;
+        let waveform_step = match waveform_hz {
+            Some(hz) if hz == 0 || hz > WAVEFORM_HZ || WAVEFORM_HZ % hz != 0 => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!["The waveform rate must divide {WAVEFORM_HZ}."],
+                ))
+            }
+            hz => hz.map(|hz| WAVEFORM_HZ / hz),
+        };
+        Ok(Self {
+            socket,
+            addr,
+            refused: 0,
+            warned: None,
+            prefix: prefix.trim_end_matches('/').to_string(),
+            waveform_step,
+            buf: vec![],
+        })
+    }
+
+    /// Sends a message to the prefixed `address`, with the given `args`.
+    fn send(&mut self, address: &str, args: &[Arg]) -> io::Result<()> {
+        let buf = &mut self.buf;
+        buf.clear();
+        push_str(buf, &format!["{}/{address}", self.prefix]);
+        let mut tags = String::from(",");
+        for arg in args {
+            tags.push(match arg {
+                Arg::Int(_) => 'i',
+                Arg::Float(_) => 'f',
+                Arg::Str(_) => 's',
+            });
+        }
+        push_str(buf, &tags);
+        for arg in args {
+            match arg {
+                Arg::Int(i) => buf.extend(i.to_be_bytes()),
+                Arg::Float(f) => buf.extend(f.to_be_bytes()),
+                Arg::Str(s) => push_str(buf, s),
+            }
+        }
+        match self.socket.send(buf) {
+            // the destination isn't listening, e.g. it hasn't started yet
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                self.refused += 1;
+                if self.warned.map_or(true, |w| w.elapsed() >= WARN_INTERVAL) {
+                    self.warned = Some(Instant::now());
+                    warn!(
+                        "Nobody is listening to the OSC messages on {} ({} refused in total).",
+                        self.addr, self.refused
+                    );
+                }
+                Ok(())
+            }
+            result => result.map(|_| ()),
+        }
+    }
+}
+
+impl Sink for OscSink {
+    fn name(&self) -> &str {
+        "osc"
+    }
+    fn write(&mut self, record: &Record) -> io::Result<()> {
+        match &record.data {
+            Ok(Data::FrequencyBins(bins)) => {
+                for (i, bin) in bins.iter().enumerate().take(FREQUENCY_BINS_LEN) {
+                    let name = FrequencyBins::from(i as u8).name();
+                    self.send(&format!["bins/{name}"], &[Arg::Int(*bin as i32)])?;
+                }
+            }
+            Ok(Data::Sqi(sqi)) => self.send("sqi", &[Arg::Int(*sqi as i32)])?,
+            Ok(Data::SleepStage(stage)) => {
+                let (code, name) = (u8::from(*stage) as i32, stage.to_string());
+                self.send("stage", &[Arg::Int(code), Arg::Str(&name)])?;
+            }
+            Ok(Data::Event(event)) => {
+                let (code, name) = (u8::from(*event) as i32, event.to_string());
+                self.send("event", &[Arg::Int(code), Arg::Str(&name)])?;
+            }
+            Ok(Data::Waveform(samples)) => {
+                if let Some(step) = self.waveform_step {
+                    let args: Vec<_> = samples
+                        .chunks(step)
+                        .map(|c| {
+                            let sum: f64 = c.iter().map(|s| waveform_uv(*s)).sum();
+                            Arg::Float((sum / c.len() as f64) as f32)
+                        })
+                        .collect();
+                    self.send("waveform", &args)?;
+                }
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+/// Appends an OSC string: null terminated, and padded to 4 bytes.
+fn push_str(buf: &mut Vec<u8>, s: &str) {
+    buf.extend(s.as_bytes());
+    let padding = 4 - s.len() % 4;
+    buf.extend(std::iter::repeat(0).take(padding));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rawzeo::SleepStages;
+    use std::time::Duration;
+
+    #[test]
+    fn padding() {
+        for (s, padded) in [
+            ("", &b"\0\0\0\0"[..]),
+            ("abc", b"abc\0"),
+            ("abcd", b"abcd\0\0\0\0"),
+            (",iff", b",iff\0\0\0\0"),
+        ] {
+            let mut buf = vec![];
+            push_str(&mut buf, s);
+            assert_eq![buf, padded];
+        }
+    }
+
+    #[test]
+    fn messages() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut osc = OscSink::new(receiver.local_addr().unwrap(), "/zeo/", Some(4)).unwrap();
+        let mut buf = [0; 1024];
+        let mut recv = || {
+            let len = receiver.recv(&mut buf).unwrap();
+            buf[..len].to_vec()
+        };
+
+        let stage = Data::SleepStage(SleepStages::Light);
+        osc.write(&Record::with_data(0, stage)).unwrap();
+        let msg = [
+            &b"/zeo/stage\0\0,is\0"[..],
+            &3_i32.to_be_bytes(),
+            b"Light\0\0\0",
+        ]
+        .concat();
+        assert_eq![recv(), msg];
+
+        // the waveform downsampled to 4 averaged samples
+        let mut samples = [0; 128];
+        samples[..32].fill(1000);
+        osc.write(&Record::with_data(0, Data::Waveform(samples)))
+            .unwrap();
+        let mut msg = b"/zeo/waveform\0\0\0,ffff\0\0\0".to_vec();
+        msg.extend((waveform_uv(1000) as f32).to_be_bytes());
+        msg.extend([0_f32; 3].iter().flat_map(|f| f.to_be_bytes()));
+        assert_eq![recv(), msg];
+
+        // the data without an OSC address is not sent
+        osc.write(&Record::with_data(0, Data::ZeoTimestamp(0)))
+            .unwrap();
+        osc.write(&Record::with_data(0, Data::Sqi(25))).unwrap();
+        assert_eq![
+            recv(),
+            [&b"/zeo/sqi\0\0\0\0,i\0\0"[..], &25_i32.to_be_bytes()].concat()
+        ];
+    }
+}
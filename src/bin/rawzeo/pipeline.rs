@@ -0,0 +1,171 @@
+// rawzeo::bin::pipeline
+//
+//! The decoding stage of the pipeline.
+//
+
+use std::sync::{mpsc::Receiver, Arc};
+
+use log::warn;
+use rawzeo::{
+    filter50hz_into, filter60hz_into, Data, Decoder, Message, Segment, Stats, FILTER50HZ_LEN,
+    FILTER60HZ_LEN, WAVEFORM_LEN,
+};
+
+use crate::{
+    metrics::Metrics,
+    reader::{Chunk, CHUNK_LEN},
+    sink::{Record, SinkThread, Skipped},
+};
+
+/// The capacity of the decoder buffer.
+///
+/// After decoding all the frames there's always room left for a whole chunk.
+const DECODER_CAP: usize = rawzeo::MAX_FRAME_LEN + CHUNK_LEN;
+
+/// A consumer of the received chunks, before they're decoded.
+pub trait Tap {
+    /// Sends a `chunk` to the consumer, without blocking.
+    fn send(&self, chunk: &Chunk);
+}
+
+/// A filter of a signal into an output longer by the length of the filter minus one.
+type FilterFn = fn(&[f64], &mut [f64]);
+
+/// Filters out the mains noise from the decoded waveform, second by second.
+///
+/// Each second is filtered along with the end of the previous one,
+/// so the filtered waveform is delayed by half the length of the filter.
+pub struct MainsFilter {
+    filter: FilterFn,
+    // the end of the previous second, followed by the current one
+    input: Vec<f64>,
+    output: Vec<f64>,
+}
+
+impl MainsFilter {
+    /// Returns a new filter of the mains noise at `hz`, either 50 or 60.
+    pub fn new(hz: u32) -> Option<MainsFilter> {
+        let (filter, len): (FilterFn, usize) = match hz {
+            50 => (filter50hz_into, FILTER50HZ_LEN),
+            60 => (filter60hz_into, FILTER60HZ_LEN),
+            _ => return None,
+        };
+        let input_len = len - 1 + WAVEFORM_LEN;
+        Some(MainsFilter {
+            filter,
+            input: vec![0.0; input_len],
+            output: vec![0.0; input_len + len - 1],
+        })
+    }
+
+    /// Filters the `data`, if it's a waveform.
+    fn apply(&mut self, data: &mut Data) {
+        let samples = match data {
+            Data::Waveform(samples) => samples,
+            _ => return,
+        };
+        let history = self.input.len() - WAVEFORM_LEN;
+        self.input.copy_within(WAVEFORM_LEN.., 0);
+        for (x, s) in self.input[history..].iter_mut().zip(samples.iter()) {
+            *x = *s as f64;
+        }
+        (self.filter)(&self.input, &mut self.output);
+        // only the output of the whole filter over the input
+        for (s, y) in samples.iter_mut().zip(&self.output[history..]) {
+            *s = y.round().clamp(i16::MIN as f64, i16::MAX as f64) as i16;
+        }
+    }
+
+    /// Forgets the previous samples, e.g. after a gap in the waveform.
+    fn reset(&mut self) {
+        self.input.iter_mut().for_each(|x| *x = 0.0);
+    }
+}
+
+/// Sends the received chunks to all the taps, and then decodes them and sends
+/// each decoded frame, and any skipped bytes, to all the sinks,
+/// until the queue of chunks is closed, updating the `metrics` along the way.
+///
+/// The decoded waveform is filtered by the mains `filter`, if any,
+/// which is reset after each reconnection and each gap in the sequence.
+///
+/// Returns the statistics of the decoded byte stream.
+pub fn run(
+    chunks: Receiver<Chunk>,
+    taps: &[Box<dyn Tap>],
+    sinks: &[SinkThread],
+    metrics: &Metrics,
+    mut filter: Option<MainsFilter>,
+) -> Stats {
+    let mut decoder = Decoder::<DECODER_CAP>::new();
+
+    for chunk in chunks {
+        for tap in taps {
+            tap.send(&chunk);
+        }
+
+        // the interrupted frame and the sequence of the old source are gone
+        if chunk.reconnected {
+            decoder.reset();
+            metrics.reconnected();
+            if let Some(filter) = &mut filter {
+                filter.reset();
+            }
+        }
+
+        let n = chunk.bytes.len();
+        let pushed = decoder.push(&chunk.bytes);
+        if pushed < n {
+            warn!(
+                "OVERRUN: dropped {} bytes ({} in total).",
+                n - pushed,
+                decoder.stats().overrun_bytes
+            );
+        }
+
+        while let Some(segment) = decoder.decode_segment() {
+            match segment {
+                Segment::Frame(frame) => {
+                    let mut data = frame.decode();
+                    match &mut data {
+                        Ok(data) => {
+                            if let Some(filter) = &mut filter {
+                                // the lost frames may have been of the waveform
+                                if frame.lost > 0 {
+                                    filter.reset();
+                                }
+                                filter.apply(data);
+                            }
+                        }
+                        Err(e) => warn!("Failed to decode {}. Error: {}", frame.header.datatype, e),
+                    }
+                    let record = Arc::new(Record {
+                        received: chunk.time,
+                        header: frame.header,
+                        msg: Message::from(&frame),
+                        data,
+                    });
+                    metrics.record(&record);
+                    for sink in sinks {
+                        sink.send(record.clone());
+                    }
+                }
+                Segment::Skipped { bytes, error } => {
+                    if let Some(e) = error {
+                        warn!("{e}");
+                    }
+                    let skipped = Arc::new(Skipped {
+                        received: chunk.time,
+                        bytes: bytes.to_vec(),
+                        error,
+                    });
+                    for sink in sinks {
+                        sink.send_skipped(skipped.clone());
+                    }
+                }
+            }
+        }
+        metrics.set_stats(decoder.stats());
+    }
+    decoder.stats()
+}
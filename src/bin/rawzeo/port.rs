@@ -0,0 +1,78 @@
+// rawzeo::bin::port
+//
+//! The serial ports.
+//
+
+use std::{
+    io::{self, Read, Write},
+    time::Duration,
+};
+
+use log::info;
+use serialport::{Parity, SerialPortType, StopBits};
+
+/// The default serial port.
+pub const PORT: &str = "/dev/ttyUSB0";
+
+/// The baud rate of the serial port.
+pub const BAUD_RATE: u32 = 38400;
+
+/// Opens the serial `port`, or the USB one with the given `serial_number`.
+pub fn open(port: &str, serial_number: Option<&str>) -> io::Result<Box<dyn Read + Send>> {
+    let path = match serial_number {
+        Some(serial_number) => serialport::available_ports()?
+            .into_iter()
+            .find(|p| match &p.port_type {
+                SerialPortType::UsbPort(usb) => usb.serial_number.as_deref() == Some(serial_number),
+                _ => false,
+            })
+            .map(|p| p.port_name)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!["No serial port with the serial number \"{serial_number}\"."],
+                )
+            })?,
+        None => port.to_string(),
+    };
+    let port = serialport::new(&path, BAUD_RATE)
+        .parity(Parity::None)
+        .stop_bits(StopBits::One)
+        .timeout(Duration::from_millis(10))
+        .open()?;
+    info!("Receiving data on {} at {} baud:", path, BAUD_RATE);
+    Ok(Box::new(port))
+}
+
+/// Prints the available serial ports, with the details of the USB ones.
+pub fn list(out: &mut impl Write) -> io::Result<()> {
+    let ports = serialport::available_ports()?;
+    if ports.is_empty() {
+        writeln!(out, "No serial ports found.")?;
+    }
+    for port in ports {
+        match port.port_type {
+            SerialPortType::UsbPort(usb) => {
+                write!(
+                    out,
+                    "{}  USB {:04x}:{:04x}",
+                    port.port_name, usb.vid, usb.pid
+                )?;
+                for (name, value) in [
+                    ("serial number", usb.serial_number),
+                    ("manufacturer", usb.manufacturer),
+                    ("product", usb.product),
+                ] {
+                    if let Some(value) = value {
+                        write!(out, ", {name}: {value}")?;
+                    }
+                }
+                writeln!(out)?;
+            }
+            SerialPortType::PciPort => writeln!(out, "{}  PCI", port.port_name)?,
+            SerialPortType::BluetoothPort => writeln!(out, "{}  Bluetooth", port.port_name)?,
+            SerialPortType::Unknown => writeln!(out, "{}", port.port_name)?,
+        }
+    }
+    Ok(())
+}
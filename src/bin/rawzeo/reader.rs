@@ -0,0 +1,313 @@
+// rawzeo::bin::reader
+//
+//! The reader thread of the source of bytes.
+//
+
+use std::{
+    io::{self, Read},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use log::{error, info, trace, warn};
+
+/// The maximum number of bytes of each chunk.
+pub const CHUNK_LEN: usize = 512;
+
+/// The initial delay before reopening a source.
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The maximum delay before reopening a source.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// The maximum time to notice that the reader was asked to stop,
+/// while waiting to reopen a source.
+const STOP_CHECK: Duration = Duration::from_millis(100);
+
+/// A chunk of bytes read from the source.
+#[derive(Clone, Debug)]
+pub struct Chunk {
+    /// The time when the bytes were received.
+    pub time: SystemTime,
+
+    /// The received bytes.
+    pub bytes: Vec<u8>,
+
+    /// Whether the source was reopened right before these bytes.
+    pub reconnected: bool,
+}
+
+/// The reader thread of the source of bytes, a serial port or a TCP stream.
+///
+/// It only reads the source and sends the timestamped chunks of bytes through
+/// a bounded queue, so that no slow consumer can stall the serial reads.
+/// When the queue is full the chunks are dropped and counted instead.
+pub struct Reader {
+    /// The receiving end of the queue of chunks.
+    pub chunks: Receiver<Chunk>,
+
+    /// The number of bytes dropped because the queue was full.
+    pub dropped: Arc<AtomicU64>,
+
+    /// Whether to stop reading, closing the queue.
+    pub stop: Arc<AtomicBool>,
+
+    /// The number of iterations of the reader, which keeps increasing
+    /// even when no bytes are received, unless the reader is stuck.
+    pub heartbeat: Arc<AtomicU64>,
+}
+
+/// The sending side of the reader thread.
+struct Output {
+    tx: SyncSender<Chunk>,
+    dropped: Arc<AtomicU64>,
+    stop: Arc<AtomicBool>,
+    heartbeat: Arc<AtomicU64>,
+    // whether to wait for room in the queue instead of dropping chunks
+    blocking: bool,
+}
+
+impl Output {
+    /// Returns whether the reader has been asked to stop,
+    /// counting a heartbeat.
+    fn stopped(&self) -> bool {
+        self.heartbeat.fetch_add(1, Ordering::Relaxed);
+        self.stop.load(Ordering::Relaxed)
+    }
+
+    /// Sleeps for the given `duration`, or until the reader is asked to stop.
+    fn sleep(&self, duration: Duration) {
+        let start = Instant::now();
+        while !self.stopped() && start.elapsed() < duration {
+            thread::sleep(STOP_CHECK.min(duration));
+        }
+    }
+}
+
+/// Opens the source of bytes.
+pub type Open = Box<dyn FnMut() -> io::Result<Box<dyn Read + Send>> + Send>;
+
+impl Reader {
+    /// Spawns a new thread reading from the `source` until it ends,
+    /// with a queue that can hold up to `capacity` chunks.
+    pub fn spawn(source: Box<dyn Read + Send>, capacity: usize) -> io::Result<Reader> {
+        Reader::spawn_until_end(source, capacity, false)
+    }
+
+    /// Spawns a new thread reading from the `source` until it ends,
+    /// with a queue that can hold up to `capacity` chunks.
+    ///
+    /// Unlike [`spawn`][Reader::spawn], it waits for room in the queue instead
+    /// of dropping chunks, which suits sources that don't stream in real time,
+    /// like files.
+    pub fn spawn_blocking(source: Box<dyn Read + Send>, capacity: usize) -> io::Result<Reader> {
+        Reader::spawn_until_end(source, capacity, true)
+    }
+
+    fn spawn_until_end(
+        mut source: Box<dyn Read + Send>,
+        capacity: usize,
+        blocking: bool,
+    ) -> io::Result<Reader> {
+        Reader::spawn_with(capacity, blocking, move |out| {
+            match read_loop(&mut *source, out, false) {
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => info!("{e}"),
+                Err(e) => error!("Failed to read the source. Error: {}", e),
+                Ok(()) => (),
+            }
+        })
+    }
+
+    /// Spawns a new thread reading from the source opened by `open`,
+    /// with a queue that can hold up to `capacity` chunks.
+    ///
+    /// Whenever the source can't be opened, or it fails or ends, e.g. because
+    /// the device was unplugged, it's closed and opened again with backoff.
+    /// The first chunk read after reopening it is marked as `reconnected`.
+    pub fn spawn_reopening(mut open: Open, capacity: usize) -> io::Result<Reader> {
+        Reader::spawn_with(capacity, false, move |out| {
+            let mut backoff = MIN_BACKOFF;
+            // the time when the source was lost
+            let mut lost: Option<Instant> = None;
+            while !out.stopped() {
+                match open() {
+                    Ok(mut source) => {
+                        if let Some(lost) = lost {
+                            info!("Reopened the source after a gap of {:.1?}.", lost.elapsed());
+                        }
+                        backoff = MIN_BACKOFF;
+                        match read_loop(&mut *source, out, lost.is_some()) {
+                            Ok(()) => return,
+                            Err(e) => warn!("Lost the source, reopening it. Error: {}", e),
+                        }
+                        lost = Some(Instant::now());
+                    }
+                    Err(e) => {
+                        warn!("Failed to open the source, retrying in {backoff:?}. Error: {e}");
+                        out.sleep(backoff);
+                        backoff = (backoff * 2).min(MAX_BACKOFF);
+                    }
+                }
+            }
+        })
+    }
+
+    /// Spawns the reader thread running `read`,
+    /// with a queue that can hold up to `capacity` chunks.
+    fn spawn_with<F>(capacity: usize, blocking: bool, read: F) -> io::Result<Reader>
+    where
+        F: FnOnce(&Output) + Send + 'static,
+    {
+        let (tx, chunks) = mpsc::sync_channel(capacity);
+        let out = Output {
+            tx,
+            dropped: Arc::new(AtomicU64::new(0)),
+            stop: Arc::new(AtomicBool::new(false)),
+            heartbeat: Arc::new(AtomicU64::new(0)),
+            blocking,
+        };
+        let (dropped, stop) = (out.dropped.clone(), out.stop.clone());
+        let heartbeat = out.heartbeat.clone();
+        thread::Builder::new()
+            .name("reader".into())
+            .spawn(move || read(&out))?;
+        Ok(Reader {
+            chunks,
+            dropped,
+            stop,
+            heartbeat,
+        })
+    }
+}
+
+/// Reads the `source` until either the reader is asked to stop
+/// or the receiving end of the queue is dropped,
+/// or returns the error that stopped the reading, including its end.
+///
+/// The first chunk sent is marked as `reconnected` if requested.
+fn read_loop(source: &mut dyn Read, out: &Output, mut reconnected: bool) -> io::Result<()> {
+    let mut buffer = [0; CHUNK_LEN];
+    while !out.stopped() {
+        match source.read(&mut buffer) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "The end of the stream was reached.",
+                ))
+            }
+            Ok(n) => {
+                trace!("READ {n} bytes");
+                let chunk = Chunk {
+                    time: SystemTime::now(),
+                    bytes: buffer[..n].to_vec(),
+                    reconnected,
+                };
+                if out.blocking {
+                    match out.tx.send(chunk) {
+                        Ok(()) => reconnected = false,
+                        Err(_) => return Ok(()),
+                    }
+                    continue;
+                }
+                match out.tx.try_send(chunk) {
+                    Ok(()) => reconnected = false,
+                    Err(TrySendError::Full(_)) => {
+                        let total = out.dropped.fetch_add(n as u64, Ordering::Relaxed) + n as u64;
+                        warn!("The reader queue is full, dropped {n} bytes ({total} in total).");
+                    }
+                    Err(TrySendError::Disconnected(_)) => return Ok(()),
+                }
+            }
+            Err(ref e) if is_transient(e) => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Returns whether the read error `e` is worth retrying.
+fn is_transient(e: &io::Error) -> bool {
+    use io::ErrorKind::*;
+    matches!(e.kind(), TimedOut | WouldBlock | Interrupted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::VecDeque, io::Cursor};
+
+    /// A source returning each of its reads in turn, and then its end.
+    struct Script(VecDeque<io::Result<&'static [u8]>>);
+
+    impl Read for Script {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.pop_front() {
+                Some(Ok(bytes)) => {
+                    buf[..bytes.len()].copy_from_slice(bytes);
+                    Ok(bytes.len())
+                }
+                Some(Err(e)) => Err(e),
+                None => Ok(0),
+            }
+        }
+    }
+
+    #[test]
+    fn read_to_end() {
+        let mut source = Script(
+            [
+                Ok(&b"ab"[..]),
+                Err(io::ErrorKind::Interrupted.into()),
+                Ok(b"cd"),
+            ]
+            .into(),
+        );
+        let (tx, rx) = mpsc::sync_channel(1);
+        let out = Output {
+            tx,
+            dropped: Arc::new(AtomicU64::new(0)),
+            stop: Arc::new(AtomicBool::new(false)),
+            heartbeat: Arc::new(AtomicU64::new(0)),
+            blocking: false,
+        };
+
+        // the transient errors are retried, and the chunks that don't fit
+        // in the queue are dropped
+        let error = read_loop(&mut source, &out, true).unwrap_err();
+        assert_eq![error.kind(), io::ErrorKind::UnexpectedEof];
+        let chunk = rx.try_recv().unwrap();
+        assert_eq![(chunk.bytes, chunk.reconnected), (b"ab".to_vec(), true)];
+        assert![rx.try_recv().is_err()];
+        assert_eq![out.dropped.load(Ordering::Relaxed), 2];
+    }
+
+    #[test]
+    fn reopen() {
+        let mut opened = 0;
+        let open: Open = Box::new(move || {
+            opened += 1;
+            Ok(match opened {
+                1 => Box::new(Cursor::new(b"ab")),
+                2 => Box::new(Cursor::new(b"cd")),
+                // until the receiving end of the queue is dropped
+                _ => Box::new(io::repeat(0)),
+            })
+        });
+        let reader = Reader::spawn_reopening(open, 4).unwrap();
+
+        let chunks: Vec<_> = reader.chunks.iter().take(3).collect();
+        let chunks: Vec<_> = chunks
+            .iter()
+            .map(|c| (&c.bytes[..], c.reconnected))
+            .collect();
+        assert_eq![chunks[..2], [(&b"ab"[..], false), (b"cd", true)]];
+        // the first chunk after reopening the source again
+        assert![chunks[2].1];
+        assert_eq![chunks[2].0, [0; CHUNK_LEN]];
+    }
+}
@@ -0,0 +1,211 @@
+// rawzeo::bin::report
+//
+//! The report of a recorded night.
+//
+
+use std::io::{self, Write};
+
+use rawzeo::{Data, Decoder, EventType, Segment, SleepStages, Stats, SLEEP_STAGE_SECS};
+
+use crate::{metrics::hms, nightly::date};
+
+/// Prints the report of the night recorded in `bytes`.
+pub fn report(bytes: &[u8], out: &mut impl Write) -> io::Result<()> {
+    let mut night = Night::default();
+    let mut decoder: Decoder = Decoder::new();
+    let mut rest = bytes;
+    loop {
+        let n = decoder.push(&rest[..rest.len().min(decoder.available())]);
+        rest = &rest[n..];
+        while let Some(segment) = decoder.decode_segment() {
+            if let Segment::Frame(frame) = segment {
+                if let Ok(data) = frame.decode() {
+                    night.add(frame.time, data);
+                }
+            }
+        }
+        if rest.is_empty() {
+            break;
+        }
+    }
+    night.write(out, decoder.stats())
+}
+
+/// The summary of a night.
+#[derive(Clone, Debug, Default)]
+struct Night {
+    // the first and last Zeo times
+    start: Option<u32>,
+    end: Option<u32>,
+    stages: Vec<SleepStages>,
+    events: Vec<(u32, EventType)>,
+    sqi_sum: u64,
+    sqi_count: u64,
+}
+
+impl Night {
+    /// Adds the decoded `data` of a frame sent at the Zeo `time`.
+    ///
+    /// The frames before the first timestamp are skipped, since their time
+    /// is unset: 0, or 1 when its low byte happens to be 1.
+    fn add(&mut self, time: u32, data: Data) {
+        if time <= 1 {
+            return;
+        }
+        self.start.get_or_insert(time);
+        self.end = Some(time);
+        match data {
+            Data::SleepStage(stage) => self.stages.push(stage),
+            Data::Event(event) => self.events.push((time, event)),
+            Data::Sqi(sqi) => {
+                self.sqi_sum += sqi as u64;
+                self.sqi_count += 1;
+            }
+            _ => (),
+        }
+    }
+
+    /// Writes the report, including the `stats` of the decoded byte stream.
+    fn write(&self, out: &mut impl Write, stats: Stats) -> io::Result<()> {
+        use SleepStages::*;
+
+        let (start, end) = match (self.start, self.end) {
+            (Some(start), Some(end)) => (start, end),
+            _ => return writeln!(out, "No timestamped frames found."),
+        };
+        writeln!(
+            out,
+            "Night of {}, from {} to {} ({})",
+            date(start as u64),
+            time_of_day(start),
+            time_of_day(end),
+            hms(end.saturating_sub(start) as u64)
+        )?;
+
+        let stage_time = |n: usize| hms(n as u64 * SLEEP_STAGE_SECS as u64);
+        let is_sleep = |s: &SleepStages| matches!(s, Rem | Light | Deep);
+        write!(out, "Stages:")?;
+        for (i, stage) in [Awake, Rem, Light, Deep, Undefined].iter().enumerate() {
+            let n = self.stages.iter().filter(|s| *s == stage).count();
+            let percent = n as f64 * 100. / self.stages.len().max(1) as f64;
+            let sep = if i == 0 { " " } else { ", " };
+            write!(out, "{sep}{stage} {} ({percent:.0}%)", stage_time(n))?;
+        }
+        writeln!(out)?;
+        let sleep = self.stages.iter().filter(|s| is_sleep(s)).count();
+        writeln!(out, "Total sleep time: {}", stage_time(sleep))?;
+        match self.stages.iter().position(is_sleep) {
+            Some(i) => writeln!(out, "Sleep onset latency: {}", stage_time(i))?,
+            None => writeln!(out, "Sleep onset latency: never fell asleep")?,
+        }
+        let awakenings = self
+            .stages
+            .windows(2)
+            .filter(|w| is_sleep(&w[0]) && w[1] == Awake)
+            .count();
+        writeln!(out, "Awakenings: {awakenings}")?;
+        if self.sqi_count > 0 {
+            let sqi = self.sqi_sum as f64 / self.sqi_count as f64;
+            writeln!(out, "Average signal quality: {sqi:.1}")?;
+        }
+        writeln!(
+            out,
+            "Link: {} frames, {} lost sequences, {} invalid checksums, {} bytes skipped",
+            stats.frames, stats.lost_sequences, stats.invalid_checksums, stats.skipped_bytes
+        )?;
+
+        writeln!(out, "Events:")?;
+        for (time, event) in &self.events {
+            writeln!(out, "  {} {event}", time_of_day(*time))?;
+        }
+        Ok(())
+    }
+}
+
+/// Formats the time of day of a Zeo time as `HH:MM:SS`.
+fn time_of_day(time: u32) -> String {
+    let secs = time % 86400;
+    format!["{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn night() {
+        use SleepStages::*;
+
+        let mut night = Night::default();
+        // 2023-02-01 22:00
+        let t = 1_675_288_800;
+        night.add(t, Data::Event(EventType::NightStart));
+        let stages = [
+            Awake, Awake, Light, Light, Light, Light, Deep, Deep, Awake, Rem,
+        ];
+        for (i, stage) in stages.into_iter().enumerate() {
+            let time = t + 30 * (i as u32 + 1);
+            night.add(time, Data::SleepStage(stage));
+            night.add(time, Data::Sqi(20 + i as u32 % 2));
+        }
+        night.add(t + 330, Data::Event(EventType::NightEnd));
+
+        let stats = Stats {
+            frames: 23,
+            ..Stats::default()
+        };
+        let mut out = vec![];
+        night.write(&mut out, stats).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq![
+            out.lines().collect::<Vec<_>>(),
+            [
+                "Night of 2023-02-01, from 22:00:00 to 22:05:30 (0:05:30)",
+                "Stages: Awake 0:01:30 (30%), Rem 0:00:30 (10%), Light 0:02:00 (40%), \
+                 Deep 0:01:00 (20%), Undefined 0:00:00 (0%)",
+                "Total sleep time: 0:03:30",
+                "Sleep onset latency: 0:01:00",
+                "Awakenings: 1",
+                "Average signal quality: 20.5",
+                "Link: 23 frames, 0 lost sequences, 0 invalid checksums, 0 bytes skipped",
+                "Events:",
+                "  22:00:00 NightStart",
+                "  22:05:30 NightEnd",
+            ]
+        ];
+    }
+
+    #[test]
+    fn never_asleep() {
+        let mut night = Night::default();
+        night.add(1_675_288_800, Data::SleepStage(SleepStages::Awake));
+        let mut out = vec![];
+        night.write(&mut out, Stats::default()).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert![out.contains("Sleep onset latency: never fell asleep\n")];
+        assert![out.contains("Awakenings: 0\n")];
+        assert![!out.contains("Average signal quality")];
+
+        let mut out = vec![];
+        Night::default().write(&mut out, Stats::default()).unwrap();
+        assert_eq![out, b"No timestamped frames found.\n"];
+    }
+
+    #[test]
+    fn unset_time() {
+        let mut night = Night::default();
+        // the frames before the first timestamp
+        night.add(0, Data::SleepStage(SleepStages::Awake));
+        night.add(1, Data::Event(EventType::NightStart));
+        night.add(1_675_288_830, Data::SleepStage(SleepStages::Light));
+        // the clock going backwards
+        night.add(1_675_288_800, Data::SleepStage(SleepStages::Light));
+        assert_eq![night.stages.len(), 2];
+        assert![night.events.is_empty()];
+
+        let mut out = vec![];
+        night.write(&mut out, Stats::default()).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert![out.starts_with("Night of 2023-02-01, from 22:00:30 to 22:00:00 (0:00:00)\n")];
+    }
+}
@@ -0,0 +1,226 @@
+// rawzeo::bin::server
+//
+//! The TCP servers sharing the data with the local network.
+//
+
+use std::{
+    io::{self, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use log::{debug, error, info, warn};
+
+use crate::{
+    pipeline::Tap,
+    reader::Chunk,
+    sink::{write_json, Record, Sink},
+};
+
+/// The maximum number of messages queued for each client.
+const CLIENT_QUEUE_LEN: usize = 1024;
+
+/// A client connected to a server.
+struct Client<M> {
+    addr: SocketAddr,
+    tx: SyncSender<M>,
+    dropped: u64,
+}
+
+/// Serves a client `stream` with the messages received through a queue.
+pub type Serve<M> = fn(TcpStream, Receiver<M>);
+
+/// Broadcasts messages to any number of TCP clients.
+///
+/// Each client is served from its own thread, fed through a bounded queue,
+/// so that a slow client never stalls the others. When its queue is full
+/// the messages are dropped and counted instead.
+#[derive(Clone)]
+pub struct Broadcast<M> {
+    name: &'static str,
+    clients: Arc<Mutex<Vec<Client<M>>>>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Broadcast<Arc<[u8]>> {
+    /// Listens on `addr` for clients that receive the messages unmodified.
+    pub fn listen<A: ToSocketAddrs>(name: &'static str, addr: A) -> io::Result<Self> {
+        Self::listen_with(name, addr, client_loop)
+    }
+}
+
+impl<M: Clone + Send + 'static> Broadcast<M> {
+    /// Listens on `addr` and spawns the thread accepting the clients,
+    /// each of them served by `serve` from its own thread.
+    pub fn listen_with<A: ToSocketAddrs>(
+        name: &'static str,
+        addr: A,
+        serve: Serve<M>,
+    ) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        info!("Serving {name} data on {}", listener.local_addr()?);
+        let broadcast = Broadcast {
+            name,
+            clients: Arc::new(Mutex::new(vec![])),
+            dropped: Arc::new(AtomicU64::new(0)),
+        };
+        let accepting = broadcast.clone();
+        thread::Builder::new()
+            .name(format!["{name}-server"])
+            .spawn(move || accepting.accept_loop(listener, serve))?;
+        Ok(broadcast)
+    }
+
+    /// Returns the total number of messages dropped for all the clients.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Sends a `message` to all the connected clients, without blocking.
+    pub fn send(&self, message: M) {
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        clients.retain_mut(|client| match client.tx.try_send(message.clone()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                client.dropped += 1;
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                warn!(
+                    "The queue of the {} client {} is full ({} messages dropped).",
+                    self.name, client.addr, client.dropped
+                );
+                true
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                info!("The {} client {} disconnected.", self.name, client.addr);
+                false
+            }
+        });
+    }
+
+    /// Accepts the clients, spawning a thread for each one.
+    fn accept_loop(&self, listener: TcpListener, serve: Serve<M>) {
+        for stream in listener.incoming() {
+            let result = stream.and_then(|stream| {
+                let addr = stream.peer_addr()?;
+                let (tx, rx) = mpsc::sync_channel(CLIENT_QUEUE_LEN);
+                thread::Builder::new()
+                    .name(format!["{}-client", self.name])
+                    .spawn(move || serve(stream, rx))?;
+                Ok(Client {
+                    addr,
+                    tx,
+                    dropped: 0,
+                })
+            });
+            match result {
+                Ok(client) => {
+                    info!("The {} client {} connected.", self.name, client.addr);
+                    let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+                    clients.push(client);
+                }
+                Err(e) => error!("Failed to accept a {} client. Error: {}", self.name, e),
+            }
+        }
+    }
+}
+
+/// Writes the received messages to the client `stream`,
+/// until either the queue is closed or the client disconnects.
+fn client_loop(mut stream: TcpStream, rx: Receiver<Arc<[u8]>>) {
+    for message in rx {
+        if let Err(e) = stream.write_all(&message) {
+            debug!("Failed to write to the client. Error: {}", e);
+            return;
+        }
+    }
+}
+
+/// Broadcasts the unmodified byte stream, like a serial port shared over TCP.
+impl Tap for Broadcast<Arc<[u8]>> {
+    fn send(&self, chunk: &Chunk) {
+        Broadcast::send(self, chunk.bytes.as_slice().into());
+    }
+}
+
+/// Broadcasts each decoded frame as a line of JSON.
+pub struct JsonServerSink {
+    broadcast: Broadcast<Arc<[u8]>>,
+    line: Vec<u8>,
+}
+
+impl JsonServerSink {
+    /// Returns a new sink broadcasting through `broadcast`.
+    pub fn new(broadcast: Broadcast<Arc<[u8]>>) -> Self {
+        Self {
+            broadcast,
+            line: vec![],
+        }
+    }
+}
+
+impl Sink for JsonServerSink {
+    fn name(&self) -> &str {
+        "json-server"
+    }
+    fn write(&mut self, record: &Record) -> io::Result<()> {
+        self.line.clear();
+        if write_json(&mut self.line, record)? {
+            self.broadcast.send(self.line.as_slice().into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dropped() {
+        let addr: SocketAddr = "127.0.0.1:9".parse().unwrap();
+        let (slow_tx, slow_rx) = mpsc::sync_channel(2);
+        let (fast_tx, fast_rx) = mpsc::sync_channel(CLIENT_QUEUE_LEN);
+        let broadcast = Broadcast {
+            name: "test",
+            clients: Arc::new(Mutex::new(vec![
+                Client {
+                    addr,
+                    tx: slow_tx,
+                    dropped: 0,
+                },
+                Client {
+                    addr,
+                    tx: fast_tx,
+                    dropped: 0,
+                },
+            ])),
+            dropped: Arc::new(AtomicU64::new(0)),
+        };
+
+        // the messages that don't fit in the queue of the slow client are
+        // dropped for it alone
+        for i in 0..5 {
+            broadcast.send(i);
+        }
+        assert_eq![broadcast.dropped(), 3];
+        assert_eq![slow_rx.try_iter().collect::<Vec<u8>>(), [0, 1]];
+        assert_eq![fast_rx.try_iter().count(), 5];
+        {
+            let clients = broadcast.clients.lock().unwrap();
+            assert_eq![clients[0].dropped, 3];
+            assert_eq![clients[1].dropped, 0];
+        }
+
+        // the disconnected clients are removed
+        drop(slow_rx);
+        broadcast.send(5);
+        assert_eq![broadcast.clients.lock().unwrap().len(), 1];
+        assert_eq![fast_rx.try_iter().count(), 1];
+        assert_eq![broadcast.dropped(), 3];
+    }
+}
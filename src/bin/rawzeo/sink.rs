@@ -0,0 +1,473 @@
+// rawzeo::bin::sink
+//
+//! The output sinks of the decoded frames.
+//
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::SystemTime,
+};
+
+use log::{error, warn};
+#[cfg(feature = "sqlite")]
+use rawzeo::SqliteStore;
+use rawzeo::{CsvExporter, Data, EdfExporter, Error, FrameHeader, Message, PcapExporter};
+use serde::Serialize;
+
+/// A decoded frame, as received by the sinks.
+#[derive(Clone, Debug)]
+pub struct Record {
+    /// The time when the last byte of the frame was received.
+    pub received: SystemTime,
+
+    /// The header of the frame.
+    pub header: FrameHeader,
+
+    /// The frame.
+    pub msg: Message,
+
+    /// The decoded data of the frame, or the error that prevented it.
+    pub data: Result<Data, Error>,
+}
+
+#[cfg(test)]
+impl Record {
+    /// Returns a record of the `data` received at the Zeo `time`.
+    pub fn with_data(time: u32, data: Data) -> Record {
+        let header = FrameHeader {
+            protocol: 4,
+            checksum: 0,
+            len: 1,
+            time_low: time as u8,
+            subsec: 0,
+            seqnum: 0,
+            datatype: data.datatype(),
+        };
+        let frame = rawzeo::Frame {
+            header,
+            time,
+            version: 3,
+            lost: 0,
+            data: &[],
+        };
+        Record {
+            received: SystemTime::UNIX_EPOCH,
+            header,
+            msg: Message::from(&frame),
+            data: Ok(data),
+        }
+    }
+
+    /// Returns a record of the `payload` of a `datatype` received at the Zeo `time`.
+    pub fn with_payload(time: u32, datatype: rawzeo::DataType, payload: &[u8]) -> Record {
+        let header = FrameHeader {
+            protocol: 4,
+            checksum: 0,
+            len: payload.len() as u16 + 1,
+            time_low: time as u8,
+            subsec: 0,
+            seqnum: 0,
+            datatype,
+        };
+        let frame = rawzeo::Frame {
+            header,
+            time,
+            version: 3,
+            lost: 0,
+            data: payload,
+        };
+        let msg = Message::from(&frame);
+        Record {
+            received: SystemTime::UNIX_EPOCH,
+            header,
+            data: msg.decode(),
+            msg,
+        }
+    }
+}
+
+/// Bytes skipped while looking for a valid frame.
+#[derive(Clone, Debug)]
+pub struct Skipped {
+    /// The time when the last byte was received.
+    pub received: SystemTime,
+
+    /// The skipped bytes.
+    pub bytes: Vec<u8>,
+
+    /// The error of the invalid frame starting with these bytes, if any.
+    pub error: Option<Error>,
+}
+
+/// An item sent to a sink thread.
+enum Item {
+    Record(Arc<Record>),
+    Skipped(Arc<Skipped>),
+}
+
+/// An output of the decoded frames.
+pub trait Sink: Send {
+    /// Returns the name of the sink.
+    fn name(&self) -> &str;
+
+    /// Writes a decoded frame.
+    fn write(&mut self, record: &Record) -> io::Result<()>;
+
+    /// Writes a decoded frame shared with the other sinks,
+    /// which can be kept without copying it. Written by `write` by default.
+    fn write_shared(&mut self, record: &Arc<Record>) -> io::Result<()> {
+        self.write(record)
+    }
+
+    /// Writes some skipped bytes. Ignored by default.
+    fn write_skipped(&mut self, _skipped: &Skipped) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Flushes any buffered output.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A sink running in its own thread, fed through a bounded queue.
+///
+/// When the queue is full the records are dropped and counted instead,
+/// so that a slow sink never stalls the rest of the pipeline.
+pub struct SinkThread {
+    name: String,
+    tx: SyncSender<Item>,
+    dropped: AtomicU64,
+    thread: JoinHandle<()>,
+    // whether to wait for room in the queue instead of dropping records
+    blocking: bool,
+}
+
+impl SinkThread {
+    /// Spawns a new thread for the `sink`,
+    /// with a queue that can hold up to `capacity` records.
+    pub fn spawn(sink: Box<dyn Sink>, capacity: usize) -> io::Result<SinkThread> {
+        SinkThread::spawn_with(sink, capacity, false)
+    }
+
+    /// Spawns a new thread for the `sink`,
+    /// with a queue that can hold up to `capacity` records.
+    ///
+    /// Unlike [`spawn`][SinkThread::spawn], sending waits for room in the queue
+    /// instead of dropping records, which suits replaying files.
+    pub fn spawn_blocking(sink: Box<dyn Sink>, capacity: usize) -> io::Result<SinkThread> {
+        SinkThread::spawn_with(sink, capacity, true)
+    }
+
+    fn spawn_with(
+        mut sink: Box<dyn Sink>,
+        capacity: usize,
+        blocking: bool,
+    ) -> io::Result<SinkThread> {
+        let name = sink.name().to_string();
+        let (tx, rx) = mpsc::sync_channel(capacity);
+        let thread = thread::Builder::new()
+            .name(format!["sink-{name}"])
+            .spawn(move || sink_loop(&mut *sink, rx))?;
+        Ok(SinkThread {
+            name,
+            tx,
+            dropped: AtomicU64::new(0),
+            thread,
+            blocking,
+        })
+    }
+
+    /// Returns the name of the sink.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the number of records dropped because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Sends a `record` to the sink, without blocking unless it was spawned so.
+    ///
+    /// Returns `false` if the sink thread has finished.
+    pub fn send(&self, record: Arc<Record>) -> bool {
+        self.send_item(Item::Record(record))
+    }
+
+    /// Sends some `skipped` bytes to the sink,
+    /// without blocking unless it was spawned so.
+    ///
+    /// Returns `false` if the sink thread has finished.
+    pub fn send_skipped(&self, skipped: Arc<Skipped>) -> bool {
+        self.send_item(Item::Skipped(skipped))
+    }
+
+    /// Sends an `item` to the sink, without blocking unless it was spawned so.
+    fn send_item(&self, item: Item) -> bool {
+        if self.blocking {
+            return self.tx.send(item).is_ok();
+        }
+        match self.tx.try_send(item) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                let total = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                warn!(
+                    "The {} sink queue is full ({total} records dropped).",
+                    self.name
+                );
+                true
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        }
+    }
+
+    /// Closes the queue and waits for the sink to write the pending records.
+    pub fn join(self) {
+        drop(self.tx);
+        if self.thread.join().is_err() {
+            error!("The {} sink thread panicked.", self.name);
+        }
+    }
+}
+
+/// Writes the received records to the `sink` until the queue is closed,
+/// flushing it whenever the queue is drained.
+fn sink_loop(sink: &mut dyn Sink, rx: Receiver<Item>) {
+    while let Ok(item) = rx.recv() {
+        write(sink, &item);
+        while let Ok(item) = rx.try_recv() {
+            write(sink, &item);
+        }
+        if let Err(e) = sink.flush() {
+            error!("Failed to flush the {} sink. Error: {}", sink.name(), e);
+        }
+    }
+}
+
+/// Writes an `item` to the `sink`, logging any error.
+fn write(sink: &mut dyn Sink, item: &Item) {
+    let result = match item {
+        Item::Record(record) => sink.write_shared(record),
+        Item::Skipped(skipped) => sink.write_skipped(skipped),
+    };
+    if let Err(e) = result {
+        let name = sink.name();
+        match item {
+            Item::Record(r) => error!(
+                "Failed to write {} to the {name} sink. Error: {e}",
+                r.msg.datatype
+            ),
+            Item::Skipped(_) => {
+                error!("Failed to write skipped bytes to the {name} sink. Error: {e}")
+            }
+        }
+    }
+}
+
+/// Prints the debug representation of each decoded frame.
+pub struct DebugSink;
+
+impl Sink for DebugSink {
+    fn name(&self) -> &str {
+        "debug"
+    }
+    fn write(&mut self, record: &Record) -> io::Result<()> {
+        if record.data.is_err() {
+            return Ok(());
+        }
+        writeln!(io::stdout(), "PARSED: {:?}", record.msg)
+    }
+}
+
+/// Prints each decoded frame as a line of JSON.
+pub struct JsonSink<W: Write> {
+    out: W,
+}
+
+impl<W: Write> JsonSink<W> {
+    /// Returns a new JSON sink writing to `out`.
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+}
+
+/// A decoded frame, as printed in JSON.
+#[derive(Serialize)]
+struct JsonFrame<'a> {
+    received: f64,
+    time: u32,
+    subsec: u16,
+    seqnum: u8,
+    protocol: u8,
+    version: u32,
+    #[serde(flatten)]
+    data: &'a Data,
+}
+
+impl<W: Write + Send> Sink for JsonSink<W> {
+    fn name(&self) -> &str {
+        "json"
+    }
+    fn write(&mut self, record: &Record) -> io::Result<()> {
+        write_json(&mut self.out, record).map(|_| ())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Writes a decoded frame as a line of JSON.
+///
+/// Returns `false` if nothing was written because the frame wasn't decoded.
+pub fn write_json(mut out: impl Write, record: &Record) -> io::Result<bool> {
+    let Ok(data) = &record.data else {
+        return Ok(false);
+    };
+    let msg = &record.msg;
+    let received = record
+        .received
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0.0, |d| d.as_secs_f64());
+    let json = JsonFrame {
+        received,
+        time: msg.time,
+        subsec: msg.subsec,
+        seqnum: msg.seqnum,
+        protocol: msg.protocol,
+        version: msg.version,
+        data,
+    };
+    serde_json::to_writer(&mut out, &json)?;
+    writeln!(out)?;
+    Ok(true)
+}
+
+/// Exports the decoded data as CSV tables.
+pub struct CsvSink {
+    csv: CsvExporter<BufWriter<File>>,
+}
+
+impl CsvSink {
+    /// Creates the CSV tables inside the existing `dir`.
+    pub fn create<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        Ok(Self {
+            csv: CsvExporter::create(dir)?,
+        })
+    }
+}
+
+impl Sink for CsvSink {
+    fn name(&self) -> &str {
+        "csv"
+    }
+    fn write(&mut self, record: &Record) -> io::Result<()> {
+        match &record.data {
+            Ok(data) => self.csv.write(record.msg.time, data),
+            Err(_) => Ok(()),
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.csv.flush()
+    }
+}
+
+/// Exports the waveform, the sleep stages and the events as EDF+.
+pub struct EdfSink {
+    edf: EdfExporter<BufWriter<File>>,
+}
+
+impl EdfSink {
+    /// Creates the EDF file at `path`.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self {
+            edf: EdfExporter::create(path)?,
+        })
+    }
+}
+
+impl Sink for EdfSink {
+    fn name(&self) -> &str {
+        "edf"
+    }
+    fn write(&mut self, record: &Record) -> io::Result<()> {
+        match &record.data {
+            Ok(data) => self.edf.write(record.msg.time, data),
+            Err(_) => Ok(()),
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.edf.flush()
+    }
+}
+
+/// Stores the decoded data in a SQLite database.
+#[cfg(feature = "sqlite")]
+pub struct SqliteSink {
+    store: SqliteStore,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteSink {
+    /// Opens the database at `path`, creating it if needed.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self {
+            store: SqliteStore::open(path)?,
+        })
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl Sink for SqliteSink {
+    fn name(&self) -> &str {
+        "sqlite"
+    }
+    fn write(&mut self, record: &Record) -> io::Result<()> {
+        match &record.data {
+            Ok(data) => self.store.write(record.msg.time, data),
+            Err(_) => Ok(()),
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.store.flush()
+    }
+}
+
+/// Exports the frames and the skipped bytes to a PCAP file.
+pub struct PcapSink {
+    pcap: PcapExporter<BufWriter<File>>,
+}
+
+impl PcapSink {
+    /// Creates the PCAP file at `path`.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self {
+            pcap: PcapExporter::create(path)?,
+        })
+    }
+}
+
+impl Sink for PcapSink {
+    fn name(&self) -> &str {
+        "pcap"
+    }
+    fn write(&mut self, record: &Record) -> io::Result<()> {
+        self.pcap
+            .write_frame(record.received, &record.header, record.msg.data())
+    }
+    fn write_skipped(&mut self, skipped: &Skipped) -> io::Result<()> {
+        self.pcap
+            .write_skipped(skipped.received, &skipped.bytes, skipped.error)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.pcap.flush()
+    }
+}
@@ -0,0 +1,125 @@
+// rawzeo::bin::tee
+//
+//! The pass-through of the raw byte stream to a pseudo-terminal.
+//
+
+use std::{
+    io::{self, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::{error, warn};
+use serialport::{ClearBuffer, SerialPort, TTYPort};
+
+use crate::{pipeline::Tap, reader::Chunk};
+
+/// The number of unread bytes in the pseudo-terminal above which nobody
+/// is assumed to be reading it, and they are discarded.
+const MAX_UNREAD: u32 = 1024;
+
+/// The minimum interval between the warnings about dropped bytes.
+const WARN_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Re-publishes the unmodified byte stream on a new pseudo-terminal,
+/// so that other programs can read it at the same time.
+///
+/// The bytes are written from their own thread, fed through a bounded queue.
+/// When the queue is full the bytes are dropped and counted instead.
+/// While nobody is reading the pseudo-terminal, the unread bytes are discarded
+/// and counted too, so that a later reader only receives fresh bytes.
+pub struct PtyTee {
+    path: String,
+    tx: SyncSender<Vec<u8>>,
+    dropped: Arc<Dropped>,
+}
+
+impl PtyTee {
+    /// Creates a new pseudo-terminal and spawns the thread writing to it,
+    /// with a queue that can hold up to `capacity` chunks.
+    pub fn spawn(capacity: usize) -> io::Result<PtyTee> {
+        let (master, mut slave) = TTYPort::pair()?;
+        // allow other programs to open it
+        slave.set_exclusive(false)?;
+        let path = slave.name().unwrap_or_default();
+
+        let (tx, rx) = mpsc::sync_channel(capacity);
+        let dropped = Arc::new(Dropped::default());
+        let tee_dropped = dropped.clone();
+        thread::Builder::new()
+            .name("tee".into())
+            // the slave is kept open so that the pseudo-terminal lives on
+            // between the programs reading from it
+            .spawn(move || tee_loop(master, slave, rx, &tee_dropped))?;
+        Ok(PtyTee { path, tx, dropped })
+    }
+
+    /// Returns the path of the pseudo-terminal.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+impl Tap for PtyTee {
+    fn send(&self, chunk: &Chunk) {
+        if let Err(TrySendError::Full(_)) = self.tx.try_send(chunk.bytes.clone()) {
+            self.dropped.add(chunk.bytes.len(), "The tee queue is full");
+        }
+    }
+}
+
+/// The count of the dropped bytes, with rate limited warnings.
+#[derive(Debug, Default)]
+struct Dropped {
+    total: AtomicU64,
+    // when the last warning was logged
+    warned: Mutex<Option<Instant>>,
+}
+
+impl Dropped {
+    /// Counts `n` dropped bytes, warning about them with the given `reason`
+    /// unless it was done recently.
+    fn add(&self, n: usize, reason: &str) {
+        let total = self.total.fetch_add(n as u64, Ordering::Relaxed) + n as u64;
+        let mut warned = self.warned.lock().unwrap_or_else(|e| e.into_inner());
+        if warned.map_or(true, |w| w.elapsed() >= WARN_INTERVAL) {
+            *warned = Some(Instant::now());
+            warn!("{reason}, dropped {n} bytes ({total} in total).");
+        }
+    }
+}
+
+/// Writes the received bytes to the `master` side of the pseudo-terminal,
+/// until the queue is closed.
+fn tee_loop(mut master: TTYPort, slave: TTYPort, rx: Receiver<Vec<u8>>, dropped: &Dropped) {
+    let discard = |n: u32| {
+        if let Err(e) = slave.clear(ClearBuffer::Input) {
+            error!("Failed to discard the unread bytes of the pseudo-terminal. Error: {e}");
+        }
+        dropped.add(n as usize, "Nobody is reading the pseudo-terminal");
+    };
+    for bytes in rx {
+        match slave.bytes_to_read() {
+            Ok(unread) if unread > MAX_UNREAD => discard(unread),
+            _ => (),
+        }
+        match master.write_all(&bytes) {
+            Ok(()) => (),
+            // the buffer of the pseudo-terminal is full
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+                ) =>
+            {
+                discard(slave.bytes_to_read().unwrap_or(0) + bytes.len() as u32)
+            }
+            Err(e) => error!("Failed to write to the pseudo-terminal. Error: {}", e),
+        }
+    }
+}
@@ -0,0 +1,333 @@
+// rawzeo::bin::websocket
+//
+//! The WebSocket server of the live decoded data, for browser dashboards.
+//
+
+use std::{
+    io::{self, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    sync::{mpsc::Receiver, Arc},
+    time::{Duration, Instant},
+};
+
+use log::debug;
+use rawzeo::DataType;
+use tungstenite::{
+    handshake::server::{ErrorResponse, Request, Response},
+    http::StatusCode,
+    Message,
+};
+
+use crate::{
+    server::Broadcast,
+    sink::{write_json, Record, Sink},
+};
+
+/// The dashboard page served to the plain HTTP requests.
+const DASHBOARD: &str = include_str!("dashboard.html");
+
+/// The maximum length of the headers of a request.
+const MAX_REQUEST_LEN: usize = 4096;
+
+/// The time to wait for the headers of a request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A kind of data clients can subscribe to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Topic {
+    Waveform,
+    Bins,
+    Signal,
+    Stage,
+    Events,
+}
+
+impl Topic {
+    /// Returns the topic with the given `name`.
+    fn from_name(name: &str) -> Option<Topic> {
+        Some(match name {
+            "waveform" => Topic::Waveform,
+            "bins" => Topic::Bins,
+            "signal" => Topic::Signal,
+            "stage" => Topic::Stage,
+            "events" => Topic::Events,
+            _ => return None,
+        })
+    }
+
+    /// Returns the topic of the given `datatype`.
+    fn of(datatype: DataType) -> Option<Topic> {
+        use DataType::*;
+        Some(match datatype {
+            Waveform => Topic::Waveform,
+            FrequencyBins => Topic::Bins,
+            Sqi | Impedance | BadSignal => Topic::Signal,
+            SleepStage => Topic::Stage,
+            Event => Topic::Events,
+            _ => return None,
+        })
+    }
+}
+
+/// The subscription of a client, parsed from the query of its request,
+/// e.g. `/?topics=waveform,stage&format=binary`.
+///
+/// By default it's subscribed to all the topics in JSON.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Subscription {
+    topics: Vec<Topic>,
+    binary: bool,
+}
+
+impl Subscription {
+    /// Parses the subscription from the `query` of a request.
+    ///
+    /// Returns an error on an unknown topic or format, instead of
+    /// subscribing the client to everything, or to the default format.
+    fn parse(query: &str) -> Result<Subscription, String> {
+        let mut sub = Subscription {
+            topics: vec![],
+            binary: false,
+        };
+        for (key, value) in query.split('&').filter_map(|kv| kv.split_once('=')) {
+            match key {
+                "topics" => {
+                    sub.topics = value
+                        .split(',')
+                        .map(|name| {
+                            Topic::from_name(name).ok_or_else(|| format!["Unknown topic: {name}."])
+                        })
+                        .collect::<Result<_, _>>()?;
+                }
+                "format" => {
+                    sub.binary = match value {
+                        "json" => false,
+                        "binary" => true,
+                        _ => return Err(format!["Unknown format: {value}."]),
+                    }
+                }
+                _ => (),
+            }
+        }
+        Ok(sub)
+    }
+
+    /// Returns whether the client is subscribed to the `record`.
+    fn wants(&self, record: &Record) -> bool {
+        record.data.is_ok()
+            && Topic::of(record.msg.datatype).map_or(false, |t| {
+                self.topics.is_empty() || self.topics.contains(&t)
+            })
+    }
+}
+
+/// Listens on `addr` for WebSocket clients, and serves the dashboard page
+/// to the rest of the HTTP clients.
+pub fn listen<A: ToSocketAddrs>(addr: A) -> io::Result<Broadcast<Arc<Record>>> {
+    Broadcast::listen_with("websocket", addr, serve)
+}
+
+/// Serves a client, either as a WebSocket or with the dashboard page.
+fn serve(stream: TcpStream, rx: Receiver<Arc<Record>>) {
+    match is_websocket(&stream) {
+        Ok(true) => serve_websocket(stream, rx),
+        Ok(false) => {
+            if let Err(e) = serve_dashboard(stream) {
+                debug!("Failed to serve the dashboard. Error: {}", e);
+            }
+        }
+        Err(e) => debug!("Failed to read the request. Error: {}", e),
+    }
+}
+
+/// Returns whether the request waiting in the `stream` is a WebSocket upgrade,
+/// without consuming it.
+fn is_websocket(stream: &TcpStream) -> io::Result<bool> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let start = Instant::now();
+    let mut buf = [0; MAX_REQUEST_LEN];
+    loop {
+        let n = stream.peek(&mut buf)?;
+        let request = &buf[..n];
+        let complete = request.windows(4).any(|w| w == b"\r\n\r\n");
+        if complete || n == 0 || n == buf.len() || start.elapsed() > REQUEST_TIMEOUT {
+            let request = String::from_utf8_lossy(request).to_ascii_lowercase();
+            return Ok(request.contains("upgrade: websocket"));
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Responds to a plain HTTP request with the dashboard page.
+fn serve_dashboard(mut stream: TcpStream) -> io::Result<()> {
+    let mut buf = [0; MAX_REQUEST_LEN];
+    let n = stream.read(&mut buf)?;
+    let found = buf[..n].starts_with(b"GET / ") || buf[..n].starts_with(b"GET /?");
+    let (status, body) = match found {
+        true => ("200 OK", DASHBOARD),
+        false => ("404 Not Found", "Not Found"),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: text/html; charset=utf-8\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+/// Sends the subscribed records to a WebSocket client,
+/// until either the queue is closed or the client disconnects.
+// the error type of the handshake callback is defined by tungstenite
+#[allow(clippy::result_large_err)]
+fn serve_websocket(stream: TcpStream, rx: Receiver<Arc<Record>>) {
+    let mut sub = None;
+    let callback = |req: &Request, res: Response| match Subscription::parse(
+        req.uri().query().unwrap_or_default(),
+    ) {
+        Ok(parsed) => {
+            sub = Some(parsed);
+            Ok(res)
+        }
+        Err(e) => {
+            let mut res = ErrorResponse::new(Some(e));
+            *res.status_mut() = StatusCode::BAD_REQUEST;
+            Err(res)
+        }
+    };
+    let mut ws = match tungstenite::accept_hdr(stream, callback) {
+        Ok(ws) => ws,
+        Err(e) => return debug!("Failed the WebSocket handshake. Error: {}", e),
+    };
+    let sub = match sub {
+        Some(sub) => sub,
+        None => return,
+    };
+    debug!("New WebSocket subscription: {sub:?}");
+
+    let mut buf = vec![];
+    for record in rx.iter().filter(|r| sub.wants(r)) {
+        buf.clear();
+        let message = if sub.binary {
+            encode_binary(&mut buf, &record);
+            Message::Binary(buf.clone())
+        } else {
+            // only fails on decoding errors, which are already filtered out
+            let _ = write_json(&mut buf, &record);
+            Message::Text(String::from_utf8_lossy(&buf).trim_end().to_string())
+        };
+        if let Err(e) = ws.send(message) {
+            return debug!("Failed to write to the WebSocket client. Error: {}", e);
+        }
+    }
+}
+
+/// Encodes a `record` in the compact binary format:
+///
+/// | bytes | field                          |
+/// |-------|--------------------------------|
+/// | 1     | datatype                       |
+/// | 4     | time, little endian            |
+/// | 2     | subsecond, little endian       |
+/// | n     | the data bytes, as received    |
+fn encode_binary(buf: &mut Vec<u8>, record: &Record) {
+    let msg = &record.msg;
+    buf.push(msg.datatype.into());
+    buf.extend(msg.time.to_le_bytes());
+    buf.extend(msg.subsec.to_le_bytes());
+    buf.extend(msg.data());
+}
+
+/// Broadcasts the decoded frames to the WebSocket clients.
+pub struct WebSocketSink {
+    broadcast: Broadcast<Arc<Record>>,
+}
+
+impl WebSocketSink {
+    /// Returns a new sink broadcasting through `broadcast`.
+    pub fn new(broadcast: Broadcast<Arc<Record>>) -> Self {
+        Self { broadcast }
+    }
+}
+
+impl Sink for WebSocketSink {
+    fn name(&self) -> &str {
+        "websocket"
+    }
+    fn write(&mut self, record: &Record) -> io::Result<()> {
+        self.write_shared(&Arc::new(record.clone()))
+    }
+    fn write_shared(&mut self, record: &Arc<Record>) -> io::Result<()> {
+        self.broadcast.send(record.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rawzeo::{Data, SleepStages};
+    use std::net::TcpListener;
+
+    #[test]
+    fn broadcast() {
+        // an ephemeral port
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let mut sink = WebSocketSink::new(listen(addr).unwrap());
+
+        let stream = TcpStream::connect(addr).unwrap();
+        let url = format!["ws://{addr}/?topics=stage"];
+        let (mut ws, _) = tungstenite::client(url, stream).unwrap();
+        ws.get_ref()
+            .set_read_timeout(Some(Duration::from_millis(50)))
+            .unwrap();
+
+        let sqi = Arc::new(Record::with_data(1_675_288_800, Data::Sqi(25)));
+        let stage = Record::with_data(1_675_288_800, Data::SleepStage(SleepStages::Deep));
+        let stage = Arc::new(stage);
+        // until the client is subscribed
+        let message = loop {
+            sink.write_shared(&sqi).unwrap();
+            sink.write_shared(&stage).unwrap();
+            match ws.read() {
+                Ok(message) => break message,
+                Err(tungstenite::Error::Io(e)) if e.kind() == io::ErrorKind::WouldBlock => (),
+                Err(e) => panic!("{e}"),
+            }
+        };
+        assert_eq![
+            message.into_text().unwrap(),
+            r#"{"received":0.0,"time":1675288800,"subsec":0,"seqnum":0,"protocol":4,"#.to_string()
+                + r#""version":3,"datatype":"SleepStage","data":"Deep"}"#
+        ];
+
+        // an unknown topic fails the handshake
+        let stream = TcpStream::connect(addr).unwrap();
+        let url = format!["ws://{addr}/?topics=stage,dreams"];
+        match tungstenite::client(url, stream) {
+            Err(tungstenite::HandshakeError::Failure(tungstenite::Error::Http(res))) => {
+                assert_eq![res.status(), StatusCode::BAD_REQUEST];
+            }
+            _ => panic!("the handshake didn't fail"),
+        }
+    }
+
+    #[test]
+    fn subscription() {
+        let sub = Subscription::parse("topics=stage,events&format=binary").unwrap();
+        assert_eq![sub.topics, [Topic::Stage, Topic::Events]];
+        assert![sub.binary];
+        let sub = Subscription::parse("").unwrap();
+        assert![sub.topics.is_empty() && !sub.binary];
+        assert_eq![
+            Subscription::parse("topics=stage,dreams").unwrap_err(),
+            "Unknown topic: dreams."
+        ];
+        assert_eq![
+            Subscription::parse("format=xml").unwrap_err(),
+            "Unknown format: xml."
+        ];
+    }
+}
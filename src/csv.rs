@@ -0,0 +1,225 @@
+// rawzeo::csv
+//
+//! Export of decoded data to CSV tables.
+//
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use crate::{impedance, waveform_uv, Data, FrequencyBins, FREQUENCY_BINS_LEN, WAVEFORM_HZ};
+
+/// Exports decoded data to separate CSV tables, one per kind of data.
+///
+/// Every table starts with a `time` column, with the absolute unix time
+/// in seconds, so that all of them can be aligned with each other:
+///
+/// | table      | one row per        | columns                                  |
+/// |------------|--------------------|------------------------------------------|
+/// | `waveform` | sample (1/128 sec) | `time`, `raw`, `uv` (microvolts)         |
+/// | `bins`     | second             | `time`, `delta`, … , `gamma` (raw power) |
+/// | `signal`   | second             | `time`, `sqi`, `impedance`, `bad_signal` |
+/// | `stages`   | 30sec epoch        | `time`, `stage`, `code`                  |
+/// | `events`   | event              | `time`, `event`, `code`                  |
+///
+/// The values missing from a row of the `signal` table are left empty.
+#[derive(Debug)]
+pub struct CsvExporter<W: Write> {
+    waveform: W,
+    bins: W,
+    signal: W,
+    stages: W,
+    events: W,
+
+    // the signal quality of the current slice
+    slice: Option<SliceSignal>,
+}
+
+/// The signal quality values received during a slice.
+#[derive(Clone, Copy, Debug, Default)]
+struct SliceSignal {
+    time: u32,
+    sqi: Option<u32>,
+    impedance: Option<u32>,
+    bad_signal: Option<bool>,
+}
+
+impl CsvExporter<BufWriter<File>> {
+    /// Creates the `waveform.csv`, `bins.csv`, `signal.csv`, `stages.csv`
+    /// and `events.csv` files inside the existing `dir`.
+    pub fn create<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        let dir = dir.as_ref();
+        let file =
+            |name: &str| -> io::Result<_> { Ok(BufWriter::new(File::create(dir.join(name))?)) };
+        Self::new(
+            file("waveform.csv")?,
+            file("bins.csv")?,
+            file("signal.csv")?,
+            file("stages.csv")?,
+            file("events.csv")?,
+        )
+    }
+}
+
+impl<W: Write> CsvExporter<W> {
+    /// Returns a new exporter over the given tables, writing their headers.
+    pub fn new(
+        mut waveform: W,
+        mut bins: W,
+        mut signal: W,
+        mut stages: W,
+        mut events: W,
+    ) -> io::Result<Self> {
+        writeln!(waveform, "time,raw,uv")?;
+        write!(bins, "time")?;
+        for i in 0..FREQUENCY_BINS_LEN {
+            write!(bins, ",{}", FrequencyBins::from(i as u8).name())?;
+        }
+        writeln!(bins)?;
+        writeln!(signal, "time,sqi,impedance,bad_signal")?;
+        writeln!(stages, "time,stage,code")?;
+        writeln!(events, "time,event,code")?;
+
+        Ok(Self {
+            waveform,
+            bins,
+            signal,
+            stages,
+            events,
+            slice: None,
+        })
+    }
+
+    /// Writes the `data` received at the given unix `time` in seconds.
+    pub fn write(&mut self, time: u32, data: &Data) -> io::Result<()> {
+        if let Some(slice) = self.slice {
+            if slice.time != time {
+                self.write_slice()?;
+            }
+        }
+        match data {
+            Data::Waveform(samples) => {
+                // 1/128 sec == 0.0078125 sec
+                for (i, s) in samples.iter().enumerate() {
+                    let frac = i * 10_000_000 / WAVEFORM_HZ;
+                    writeln!(self.waveform, "{time}.{frac:07},{s},{:.3}", waveform_uv(*s))?;
+                }
+            }
+            Data::FrequencyBins(bins) => {
+                write!(self.bins, "{time}")?;
+                for b in bins {
+                    write!(self.bins, ",{b}")?;
+                }
+                writeln!(self.bins)?;
+            }
+            Data::Sqi(sqi) => self.slice_at(time).sqi = Some(*sqi),
+            Data::Impedance(imp) => self.slice_at(time).impedance = Some(*imp),
+            Data::BadSignal(bad) => self.slice_at(time).bad_signal = Some(*bad),
+            Data::SleepStage(stage) => {
+                writeln!(self.stages, "{time},{stage},{}", u8::from(*stage))?;
+            }
+            Data::Event(event) => {
+                writeln!(self.events, "{time},{event},{}", u8::from(*event))?;
+            }
+            Data::SliceEnd(_) => self.write_slice()?,
+            Data::Version(_) | Data::ZeoTimestamp(_) => (),
+        }
+        Ok(())
+    }
+
+    /// Writes any pending row and flushes all the tables.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.write_slice()?;
+        self.waveform.flush()?;
+        self.bins.flush()?;
+        self.signal.flush()?;
+        self.stages.flush()?;
+        self.events.flush()
+    }
+
+    /// Returns the signal quality of the slice at `time`.
+    fn slice_at(&mut self, time: u32) -> &mut SliceSignal {
+        self.slice.get_or_insert(SliceSignal {
+            time,
+            ..Default::default()
+        })
+    }
+
+    /// Writes the current slice to the signal table, if there's one.
+    fn write_slice(&mut self) -> io::Result<()> {
+        if let Some(slice) = self.slice.take() {
+            write!(self.signal, "{},", slice.time)?;
+            if let Some(sqi) = slice.sqi {
+                write!(self.signal, "{sqi}")?;
+            }
+            write!(self.signal, ",")?;
+            if let Some(imp) = slice.impedance.and_then(impedance) {
+                write!(self.signal, "{imp:.1}")?;
+            }
+            write!(self.signal, ",")?;
+            if let Some(bad) = slice.bad_signal {
+                write!(self.signal, "{}", bad as u8)?;
+            }
+            writeln!(self.signal)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EventType, SleepStages};
+
+    #[test]
+    fn tables() {
+        let mut csv = CsvExporter::new(vec![], vec![], vec![], vec![], vec![]).unwrap();
+        let mut samples = [0; 128];
+        samples[1] = -1000;
+        for (time, data) in [
+            (100, Data::Waveform(samples)),
+            (100, Data::FrequencyBins([1, 2, 3, 4, 5, 6, 7])),
+            (100, Data::Sqi(25)),
+            // 3 and 4 from the middle of the range
+            (100, Data::Impedance(0x8004_8003)),
+            (100, Data::BadSignal(false)),
+            (100, Data::SliceEnd(100)),
+            // a new slice without its end
+            (101, Data::Sqi(20)),
+            (102, Data::Impedance(0x8000_FFFF)),
+            (102, Data::BadSignal(true)),
+            (102, Data::SleepStage(SleepStages::Light)),
+            (102, Data::Event(EventType::NightStart)),
+            (102, Data::ZeoTimestamp(102)),
+        ] {
+            csv.write(time, &data).unwrap();
+        }
+        csv.flush().unwrap();
+
+        let table = |t: &[u8]| String::from_utf8(t.to_vec()).unwrap();
+        let waveform = table(&csv.waveform);
+        let lines: Vec<_> = waveform.lines().collect();
+        assert_eq![lines.len(), 1 + 128];
+        assert_eq![
+            lines[..3],
+            [
+                "time,raw,uv",
+                "100.0000000,0,0.000",
+                "100.0078125,-1000,-9.613"
+            ]
+        ];
+        assert_eq![lines[128], "100.9921875,0,0.000"];
+        assert_eq![
+            table(&csv.bins),
+            "time,delta,theta,alpha,beta_mid,beta_high,beta_low,gamma\n100,1,2,3,4,5,6,7\n"
+        ];
+        assert_eq![
+            table(&csv.signal),
+            "time,sqi,impedance,bad_signal\n100,25,5.0,0\n101,20,,\n102,,,1\n"
+        ];
+        assert_eq![table(&csv.stages), "time,stage,code\n102,Light,3\n"];
+        assert_eq![table(&csv.events), "time,event,code\n102,NightStart,5\n"];
+    }
+}
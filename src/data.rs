@@ -0,0 +1,186 @@
+// rawzeo::data
+//
+//! Decoding of the message data payloads.
+//
+
+use crate::{DataType, Error, EventType, SleepStages};
+
+/// The number of samples in each [`DataType::Waveform`] message.
+pub const WAVEFORM_LEN: usize = 128;
+
+/// The sampling rate of the waveform, in samples per second.
+pub const WAVEFORM_HZ: usize = 128;
+
+/// The number of bins in each [`DataType::FrequencyBins`] message.
+pub const FREQUENCY_BINS_LEN: usize = 7;
+
+/// The duration of each sleep stage epoch, in seconds.
+pub const SLEEP_STAGE_SECS: u32 = 30;
+
+/// The versions of the raw data output whose payloads can be decoded.
+///
+/// Version 3 is the only one known: no other version has been seen or
+/// documented, so the payloads of any other one are not decoded.
+pub const RAW_DATA_VERSIONS: &[u32] = &[3];
+
+/// The decoded data payload of a message.
+// the waveform is stored inline in order to avoid allocating
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize),
+    serde(tag = "datatype", content = "data")
+)]
+pub enum Data {
+    /// An event has occured.
+    Event(EventType),
+
+    /// Marks the end of a slice of data.
+    SliceEnd(u32),
+
+    /// Version of the raw data output.
+    Version(u32),
+
+    /// One second of raw time domain brainwave samples.
+    Waveform(
+        #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_samples"))]
+        [i16; WAVEFORM_LEN],
+    ),
+
+    /// The frequency bins, indexed by [`FrequencyBins`][crate::FrequencyBins].
+    FrequencyBins([u16; FREQUENCY_BINS_LEN]),
+
+    /// Signal Quality Index of waveform (0..=30).
+    Sqi(u32),
+
+    /// Timestamp from Zeo’s RTC.
+    ZeoTimestamp(u32),
+
+    /// The raw impedance across the headband.
+    ///
+    /// See `impedance` for converting it to its magnitude.
+    Impedance(u32),
+
+    /// Whether the signal contains artifacts.
+    BadSignal(bool),
+
+    /// Current 30sec sleep stage.
+    SleepStage(SleepStages),
+}
+
+impl Data {
+    /// Decodes the data bytes of a message of the given `datatype`,
+    /// sent with the given `version` of the raw data output.
+    ///
+    /// A `version` of 0 means that it hasn't been received yet, in which case
+    /// the layout of the supported versions is assumed. The `Version` and
+    /// `ZeoTimestamp` payloads are always decoded, since they're needed
+    /// to reconstruct the context of the stream.
+    pub fn decode_versioned(datatype: DataType, version: u32, bytes: &[u8]) -> Result<Data, Error> {
+        let unversioned = matches!(datatype, DataType::Version | DataType::ZeoTimestamp);
+        if version != 0 && !unversioned && !RAW_DATA_VERSIONS.contains(&version) {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        Data::decode(datatype, bytes)
+    }
+
+    /// Decodes the data bytes of a message of the given `datatype`,
+    /// regardless of the version of the raw data output.
+    pub fn decode(datatype: DataType, bytes: &[u8]) -> Result<Data, Error> {
+        use DataType::*;
+        let u32_le = || u32_le(bytes).ok_or(Error::NotEnoughData(datatype));
+        Ok(match datatype {
+            Event => Data::Event(EventType::from(u32_le()? as u8)),
+            SliceEnd => Data::SliceEnd(u32_le()?),
+            Version => Data::Version(u32_le()?),
+            Waveform => {
+                if bytes.len() < WAVEFORM_LEN * 2 {
+                    return Err(Error::NotEnoughData(datatype));
+                }
+                let mut samples = [0; WAVEFORM_LEN];
+                for (s, b) in samples.iter_mut().zip(bytes.chunks_exact(2)) {
+                    *s = i16::from_le_bytes([b[0], b[1]]);
+                }
+                Data::Waveform(samples)
+            }
+            FrequencyBins => {
+                if bytes.len() < FREQUENCY_BINS_LEN * 2 {
+                    return Err(Error::NotEnoughData(datatype));
+                }
+                let mut bins = [0; FREQUENCY_BINS_LEN];
+                for (bin, b) in bins.iter_mut().zip(bytes.chunks_exact(2)) {
+                    *bin = u16::from_le_bytes([b[0], b[1]]);
+                }
+                Data::FrequencyBins(bins)
+            }
+            Sqi => Data::Sqi(u32_le()?),
+            ZeoTimestamp => Data::ZeoTimestamp(u32_le()?),
+            Impedance => Data::Impedance(u32_le()?),
+            BadSignal => Data::BadSignal(u32_le()? != 0),
+            SleepStage => Data::SleepStage(SleepStages::from(u32_le()? as u8)),
+            Invalid(b) => return Err(Error::InvalidDatatype(b)),
+        })
+    }
+
+    /// Returns the datatype of this data.
+    pub fn datatype(&self) -> DataType {
+        match self {
+            Data::Event(_) => DataType::Event,
+            Data::SliceEnd(_) => DataType::SliceEnd,
+            Data::Version(_) => DataType::Version,
+            Data::Waveform(_) => DataType::Waveform,
+            Data::FrequencyBins(_) => DataType::FrequencyBins,
+            Data::Sqi(_) => DataType::Sqi,
+            Data::ZeoTimestamp(_) => DataType::ZeoTimestamp,
+            Data::Impedance(_) => DataType::Impedance,
+            Data::BadSignal(_) => DataType::BadSignal,
+            Data::SleepStage(_) => DataType::SleepStage,
+        }
+    }
+}
+
+/// Converts a raw waveform sample to microvolts.
+pub fn waveform_uv(sample: i16) -> f64 {
+    sample as f64 * 315.0 / 32768.0
+}
+
+/// Returns the magnitude of a raw impedance value,
+/// or `None` if it's out of range.
+///
+/// The raw value contains the in-phase and quadrature components
+/// in its lower and higher 16 bits, respectively.
+#[cfg(feature = "std")]
+pub fn impedance(raw: u32) -> Option<f64> {
+    let i = (raw & 0xFFFF) as i32 - 0x8000;
+    let q = (raw >> 16) as i32 - 0x8000;
+    // 0x7FFF indicates the impedance is out of range
+    if i == 0x7FFF {
+        None
+    } else {
+        Some(((i * i + q * q) as f64).sqrt())
+    }
+}
+
+/// Serializes the waveform samples as a sequence.
+#[cfg(feature = "serde")]
+fn serialize_samples<S: serde::Serializer>(
+    samples: &[i16; WAVEFORM_LEN],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(samples)
+}
+
+/// Reads a little endian `u32` from up to 4 bytes.
+///
+/// Missing bytes are considered to be zero.
+fn u32_le(bytes: &[u8]) -> Option<u32> {
+    if bytes.is_empty() {
+        return None;
+    }
+    let mut b = [0; 4];
+    for (d, s) in b.iter_mut().zip(bytes) {
+        *d = *s;
+    }
+    Some(u32::from_le_bytes(b))
+}
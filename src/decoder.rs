@@ -0,0 +1,707 @@
+// rawzeo::decoder
+//
+//! Decoding of the messages from the serial byte stream.
+//
+
+use core::fmt;
+
+use log::{debug, trace, warn};
+
+use crate::{
+    frame::{scan, Scan},
+    Data, DataType, Error, Frame, FrameHeader, MAX_DATA_LEN, MAX_FRAME_LEN, RAW_DATA_VERSIONS,
+};
+
+/// A message decoded from the serial byte stream, owning its data bytes.
+#[derive(Clone)]
+pub struct Message {
+    /// The reconstructed unix time, in seconds.
+    pub time: u32,
+
+    /// The sub-second.
+    pub subsec: u16,
+
+    /// The sequence number.
+    pub seqnum: u8,
+
+    /// The protocol version of the frame header.
+    pub protocol: u8,
+
+    /// The version of the raw data output.
+    pub version: u32,
+
+    /// The type of the data.
+    pub datatype: DataType,
+
+    data: [u8; MAX_DATA_LEN],
+    len: u16,
+}
+
+impl Message {
+    /// Returns the data bytes.
+    pub fn data(&self) -> &[u8] {
+        &self.data[..self.len as usize]
+    }
+
+    /// Decodes the data bytes, according to the version of the raw data output.
+    pub fn decode(&self) -> Result<Data, Error> {
+        Data::decode_versioned(self.datatype, self.version, self.data())
+    }
+}
+
+impl From<&Frame<'_>> for Message {
+    fn from(frame: &Frame) -> Message {
+        let mut data = [0; MAX_DATA_LEN];
+        data[..frame.data.len()].copy_from_slice(frame.data);
+        Message {
+            time: frame.time,
+            subsec: frame.header.subsec,
+            seqnum: frame.header.seqnum,
+            protocol: frame.header.protocol,
+            version: frame.version,
+            datatype: frame.header.datatype,
+            data,
+            len: frame.data.len() as u16,
+        }
+    }
+}
+
+impl fmt::Debug for Message {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Message")
+            .field("time", &self.time)
+            .field("subsec", &self.subsec)
+            .field("seqnum", &self.seqnum)
+            .field("protocol", &self.protocol)
+            .field("version", &self.version)
+            .field("datatype", &self.datatype)
+            .field("data", &self.data())
+            .finish()
+    }
+}
+
+/// The state needed to reconstruct the full timestamp of each frame,
+/// and to detect lost and duplicate sequences.
+#[derive(Clone, Debug, Default)]
+pub struct Context {
+    prev_seqnum: Option<u8>,
+    lost_sequences: u64,
+    duplicate_sequences: u64,
+    // the most recently received RTC value
+    zeo_time: u32,
+    zeo_version: u32,
+    protocol: u8,
+}
+
+impl Context {
+    /// Returns a new empty context.
+    pub const fn new() -> Self {
+        Self {
+            prev_seqnum: None,
+            lost_sequences: 0,
+            duplicate_sequences: 0,
+            zeo_time: 0,
+            zeo_version: 0,
+            protocol: 0,
+        }
+    }
+
+    /// Returns the most recently received version of the raw data output,
+    /// or 0 if it hasn't been received yet.
+    pub fn version(&self) -> u32 {
+        self.zeo_version
+    }
+
+    /// Returns the protocol version of the most recent frame header,
+    /// or 0 if no frame has been received yet.
+    pub fn protocol(&self) -> u8 {
+        self.protocol
+    }
+
+    /// Returns the total number of lost sequences.
+    pub fn lost_sequences(&self) -> u64 {
+        self.lost_sequences
+    }
+
+    /// Returns the total number of repeated sequences.
+    pub fn duplicate_sequences(&self) -> u64 {
+        self.duplicate_sequences
+    }
+
+    /// Updates the context with the next frame `header` and `data`,
+    /// and returns the frame with its reconstructed time.
+    pub fn update<'a>(&mut self, header: FrameHeader, data: &'a [u8]) -> Frame<'a> {
+        // we shouldn't be losing any sequences (after 255 comes 0)
+        // but we do, seemingly without fault of our own…...
+        let seqnum = header.seqnum;
+        let mut lost = 0;
+        if let Some(pseq) = self.prev_seqnum {
+            let prev_seq1 = pseq.wrapping_add(1);
+            if pseq == seqnum {
+                // a repeated frame, not a whole wrap around of lost ones
+                self.duplicate_sequences += 1;
+                warn!["we've received a duplicate sequence!"];
+            } else if prev_seq1 != seqnum {
+                lost = seqnum.wrapping_sub(prev_seq1);
+                self.lost_sequences += lost as u64;
+                warn!["we've lost {} sequence(s)!", lost];
+            }
+        }
+        self.prev_seqnum = Some(seqnum);
+        self.protocol = header.protocol;
+
+        match Data::decode(header.datatype, data) {
+            Ok(Data::ZeoTimestamp(t)) => {
+                self.zeo_time = t;
+                debug!("> zeo_time: {}", self.zeo_time);
+            }
+            Ok(Data::Version(v)) => {
+                if v != self.zeo_version && !RAW_DATA_VERSIONS.contains(&v) {
+                    warn!("Unsupported raw data output version: {v}.");
+                }
+                self.zeo_version = v;
+                debug!("> zeo_version: {}", self.zeo_version);
+            }
+            _ => (),
+        }
+
+        // Construct the full timestamp from the most recently received RTC
+        // value in seconds, and the lower 8 bits of the RTC value as of
+        // when this object was sent.
+        let tt_lb = header.time_low;
+        let zeo_time = self.zeo_time;
+        let zeo_time_full;
+        if zeo_time & 0xFF == tt_lb as u32 {
+            zeo_time_full = zeo_time;
+            trace!(">> tt CHECK A")
+        } else if (zeo_time.saturating_sub(1)) & 0xFF == tt_lb as u32 {
+            zeo_time_full = zeo_time.saturating_sub(1);
+            debug!(">> tt CHECK B")
+        } else if (zeo_time.saturating_add(1)) & 0xFF == tt_lb as u32 {
+            zeo_time_full = zeo_time.saturating_add(1);
+            debug!(">> tt CHECK C")
+        } else {
+            // Something doesn't line up. Maybe unit was reset.
+            zeo_time_full = zeo_time;
+            debug!(">> tt CHECK D")
+        }
+        debug!("> zeo_time_full: {zeo_time_full} + {}", header.subsec);
+
+        // the callbacks are invoked by the `Dispatcher`.
+        Frame {
+            header,
+            time: zeo_time_full,
+            version: self.zeo_version,
+            lost,
+            data,
+        }
+    }
+}
+
+/// A segment of the byte stream, as returned by [`Decoder::decode_segment`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Segment<'a> {
+    /// A valid frame.
+    Frame(Frame<'a>),
+
+    /// Bytes skipped while looking for a valid frame,
+    /// with the error that made them invalid, if any.
+    Skipped {
+        /// The skipped bytes.
+        bytes: &'a [u8],
+        /// The error of the invalid frame starting with these bytes.
+        error: Option<Error>,
+    },
+}
+
+/// Statistics about the decoded byte stream.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// The number of valid frames decoded.
+    pub frames: u64,
+
+    /// The number of bytes discarded while looking for a valid frame.
+    pub skipped_bytes: u64,
+
+    /// The number of bytes dropped because they didn't fit in the buffer.
+    pub overrun_bytes: u64,
+
+    /// The number of frames with an invalid length.
+    pub invalid_lengths: u64,
+
+    /// The number of frames with an invalid checksum.
+    pub invalid_checksums: u64,
+
+    /// The number of frames with an unsupported protocol version.
+    pub unsupported_protocols: u64,
+
+    /// The number of lost sequences.
+    pub lost_sequences: u64,
+
+    /// The number of sequences received again right after themselves.
+    pub duplicate_sequences: u64,
+}
+
+/// Decodes frames from the serial byte stream, using a buffer of fixed
+/// capacity `CAP` that must be at least [`MAX_FRAME_LEN`] bytes.
+///
+/// The decoded frames borrow their data from the buffer, and the consumed
+/// bytes are only discarded when more room is needed to push new ones.
+///
+/// The buffer never overwrites unread bytes. In order to avoid losing data
+/// the reader should apply backpressure by not pushing more bytes than
+/// [`available`][Self::available], and decode all the frames after each push.
+/// Any bytes that don't fit are dropped and counted as overrun in the [`Stats`].
+#[derive(Clone)]
+pub struct Decoder<const CAP: usize = 1024> {
+    buf: [u8; CAP],
+    start: usize,
+    end: usize,
+    context: Context,
+    stats: Stats,
+}
+
+impl<const CAP: usize> Default for Decoder<CAP> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const CAP: usize> fmt::Debug for Decoder<CAP> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Decoder")
+            .field("buffered", &self.bytes())
+            .field("context", &self.context)
+            .field("stats", &self.stats)
+            .finish()
+    }
+}
+
+impl<const CAP: usize> Decoder<CAP> {
+    /// Returns a new empty decoder.
+    ///
+    /// # Panics
+    /// Panics if `CAP` is less than [`MAX_FRAME_LEN`].
+    pub const fn new() -> Self {
+        assert!(CAP >= MAX_FRAME_LEN, "CAP must be at least MAX_FRAME_LEN");
+        Self {
+            buf: [0; CAP],
+            start: 0,
+            end: 0,
+            context: Context::new(),
+            stats: Stats {
+                frames: 0,
+                skipped_bytes: 0,
+                overrun_bytes: 0,
+                invalid_lengths: 0,
+                invalid_checksums: 0,
+                unsupported_protocols: 0,
+                lost_sequences: 0,
+                duplicate_sequences: 0,
+            },
+        }
+    }
+
+    /// Appends as many `bytes` as there's room for in the buffer.
+    ///
+    /// Returns the number of bytes appended.
+    /// The rest of the bytes are dropped and counted as overrun.
+    pub fn push(&mut self, bytes: &[u8]) -> usize {
+        if self.end + bytes.len() > CAP && self.start > 0 {
+            self.buf.copy_within(self.start..self.end, 0);
+            self.end -= self.start;
+            self.start = 0;
+        }
+        let n = bytes.len().min(CAP - self.end);
+        self.buf[self.end..self.end + n].copy_from_slice(&bytes[..n]);
+        self.end += n;
+        self.stats.overrun_bytes += (bytes.len() - n) as u64;
+        n
+    }
+
+    /// Returns the number of buffered bytes.
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    /// Returns `true` if there are no buffered bytes.
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// Returns the number of bytes there's still room for in the buffer.
+    pub fn available(&self) -> usize {
+        CAP - self.len()
+    }
+
+    /// Returns the context of the decoded frames.
+    pub fn context(&self) -> &Context {
+        &self.context
+    }
+
+    /// Returns the statistics of the decoded byte stream.
+    pub fn stats(&self) -> Stats {
+        Stats {
+            lost_sequences: self.stats.lost_sequences + self.context.lost_sequences(),
+            duplicate_sequences: self.stats.duplicate_sequences
+                + self.context.duplicate_sequences(),
+            ..self.stats
+        }
+    }
+
+    /// Discards the buffered bytes and the context, e.g. after reconnecting
+    /// to the source, so that the next frames start a new sequence.
+    ///
+    /// The statistics are kept, with the discarded bytes counted as skipped.
+    pub fn reset(&mut self) {
+        self.stats = self.stats();
+        self.stats.skipped_bytes += self.len() as u64;
+        self.start = 0;
+        self.end = 0;
+        self.context = Context::new();
+    }
+
+    /// Returns the buffered bytes.
+    fn bytes(&self) -> &[u8] {
+        &self.buf[self.start..self.end]
+    }
+
+    /// Decodes the next frame from the buffered bytes, without copying.
+    ///
+    /// Returns `None` when there are not enough bytes for a complete frame.
+    pub fn decode_frame(&mut self) -> Result<Option<Frame<'_>>, Error> {
+        match scan(self.bytes()) {
+            Scan::Incomplete { skip } => {
+                self.skip(skip, None);
+                Ok(None)
+            }
+            Scan::Invalid { skip, error } => {
+                self.skip(skip, Some(error));
+                Err(error)
+            }
+            Scan::Frame { skip, header } => {
+                self.skip(skip, None);
+                Ok(Some(self.frame(header)))
+            }
+        }
+    }
+
+    /// Decodes the next segment from the buffered bytes, without copying.
+    ///
+    /// Unlike [`decode_frame`][Self::decode_frame], it also returns the bytes
+    /// skipped while looking for a valid frame, which is useful to inspect
+    /// the corrupted parts of the stream.
+    ///
+    /// Returns `None` when there are not enough bytes for a complete frame.
+    pub fn decode_segment(&mut self) -> Option<Segment<'_>> {
+        let (skip, error) = match scan(self.bytes()) {
+            Scan::Incomplete { skip: 0 } => return None,
+            Scan::Frame { skip: 0, header } => return Some(Segment::Frame(self.frame(header))),
+            // the bytes preceding a frame are returned first
+            Scan::Incomplete { skip } | Scan::Frame { skip, .. } => (skip, None),
+            Scan::Invalid { skip, error } => (skip, Some(error)),
+        };
+        let start = self.start;
+        self.skip(skip, error);
+        Some(Segment::Skipped {
+            bytes: &self.buf[start..start + skip],
+            error,
+        })
+    }
+
+    /// Skips some bytes, counting them along with their `error`.
+    fn skip(&mut self, skip: usize, error: Option<Error>) {
+        self.start += skip;
+        self.stats.skipped_bytes += skip as u64;
+        match error {
+            Some(Error::InvalidLength) => self.stats.invalid_lengths += 1,
+            Some(Error::InvalidChecksum) => self.stats.invalid_checksums += 1,
+            Some(Error::UnsupportedProtocol(_)) => self.stats.unsupported_protocols += 1,
+            _ => (),
+        }
+    }
+
+    /// Consumes the frame with the given `header` at the start of the buffer.
+    fn frame(&mut self, header: FrameHeader) -> Frame<'_> {
+        self.stats.frames += 1;
+        let data = self.start + crate::HEADER_LEN;
+        self.start += header.frame_len();
+        let data = &self.buf[data..data + header.data_len()];
+        self.context.update(header, data)
+    }
+
+    /// Decodes the next message from the buffered bytes.
+    ///
+    /// Returns `None` when there are not enough bytes for a complete message.
+    pub fn decode(&mut self) -> Result<Option<Message>, Error> {
+        Ok(self.decode_frame()?.as_ref().map(Message::from))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{frame::encode, SleepStages};
+    use std::vec::Vec;
+
+    /// Returns the bytes of a sleep stage frame.
+    fn stage(time: u32, seqnum: u8) -> Vec<u8> {
+        encode(4, time, seqnum, DataType::SleepStage, &[3, 0, 0, 0])
+    }
+
+    #[test]
+    fn resync() {
+        let mut decoder = Decoder::<1024>::new();
+        decoder.push(b"\x00garbage A");
+        decoder.push(&stage(0, 0));
+        decoder.push(b"AA4");
+        decoder.push(&stage(0, 1));
+
+        let msg = decoder.decode().unwrap().unwrap();
+        assert_eq![msg.seqnum, 0];
+        assert_eq![msg.decode(), Ok(Data::SleepStage(SleepStages::Light))];
+        // a stray frame start is skipped with the bytes preceding it
+        assert_eq![decoder.decode().unwrap_err(), Error::InvalidLength];
+        let msg = decoder.decode().unwrap().unwrap();
+        assert_eq![msg.seqnum, 1];
+        assert![decoder.decode().unwrap().is_none()];
+        assert![decoder.is_empty()];
+
+        let stats = decoder.stats();
+        assert_eq![stats.frames, 2];
+        assert_eq![stats.invalid_lengths, 1];
+        assert_eq![stats.skipped_bytes, 10 + 3];
+        assert_eq![stats.lost_sequences, 0];
+    }
+
+    #[test]
+    fn invalid_checksum() {
+        let mut decoder = Decoder::<1024>::new();
+        let mut bad = stage(0, 1);
+        bad[2] ^= 0xFF;
+        decoder.push(&stage(0, 0));
+        decoder.push(b"junk!");
+        decoder.push(&bad);
+        decoder.push(&stage(0, 2));
+
+        assert_eq![decoder.decode().unwrap().unwrap().seqnum, 0];
+        assert_eq![decoder.decode().unwrap_err(), Error::InvalidChecksum];
+        assert_eq![decoder.decode().unwrap().unwrap().seqnum, 2];
+        assert![decoder.decode().unwrap().is_none()];
+        assert![decoder.is_empty()];
+
+        // one lost sequence and 21 skipped bytes after a single bad frame
+        let stats = decoder.stats();
+        assert_eq![stats.frames, 2];
+        assert_eq![stats.invalid_checksums, 1];
+        assert_eq![stats.lost_sequences, 1];
+        assert_eq![stats.skipped_bytes, 5 + 16];
+    }
+
+    #[test]
+    fn invalid_length() {
+        let mut decoder = Decoder::<1024>::new();
+        let mut bad = stage(0, 0);
+        // the inverse of the length doesn't match
+        bad[5] ^= 0x01;
+        let mut long = stage(0, 1);
+        long[3..7].copy_from_slice(&[0xFF, 0x01, 0x00, 0xFE]);
+        decoder.push(&bad);
+        decoder.push(&long);
+        decoder.push(&stage(0, 2));
+
+        assert_eq![decoder.decode().unwrap_err(), Error::InvalidLength];
+        assert_eq![decoder.decode().unwrap_err(), Error::InvalidLength];
+        assert_eq![decoder.decode().unwrap().unwrap().seqnum, 2];
+
+        // the rest of each invalid frame is skipped looking for the next one
+        let stats = decoder.stats();
+        assert_eq![stats.invalid_lengths, 2];
+        assert_eq![stats.skipped_bytes, 2 * 16];
+    }
+
+    #[test]
+    fn decode_frame() {
+        let mut decoder = Decoder::<1024>::new();
+        decoder.push(&encode(4, 0, 0, DataType::Version, &[3, 0, 0, 0]));
+        decoder.push(&stage(0, 1));
+
+        let frame = decoder.decode_frame().unwrap().unwrap();
+        assert_eq![frame.decode(), Ok(Data::Version(3))];
+        let frame = decoder.decode_frame().unwrap().unwrap();
+        assert_eq![frame.header.seqnum, 1];
+        assert_eq![frame.data, [3, 0, 0, 0]];
+        assert_eq![frame.version, 3];
+        assert![decoder.decode_frame().unwrap().is_none()];
+        assert_eq![decoder.context().version(), 3];
+    }
+
+    #[test]
+    fn overrun() {
+        let mut decoder = Decoder::<300>::new();
+        let waveform = encode(4, 0, 0, DataType::Waveform, &[0; 256]);
+        assert_eq![decoder.push(&waveform), 268];
+        assert_eq![decoder.available(), 32];
+
+        // only the bytes that fit are pushed, the rest are counted as overrun
+        let stages = [stage(0, 1), stage(0, 2), stage(0, 3)].concat();
+        assert_eq![decoder.push(&stages), 32];
+        assert_eq![decoder.available(), 0];
+        assert_eq![decoder.stats().overrun_bytes, 16];
+
+        // the consumed bytes make room for more
+        assert![decoder.decode_frame().unwrap().is_some()];
+        assert_eq![decoder.len(), 32];
+        assert_eq![decoder.available(), 268];
+        assert_eq![decoder.push(&stage(0, 3)), 16];
+        for seqnum in 1..=3 {
+            assert_eq![
+                decoder.decode_frame().unwrap().unwrap().header.seqnum,
+                seqnum
+            ];
+        }
+        assert![decoder.is_empty()];
+
+        let stats = decoder.stats();
+        assert_eq![stats.frames, 4];
+        assert_eq![stats.skipped_bytes, 0];
+        assert_eq![stats.lost_sequences, 0];
+    }
+
+    #[test]
+    fn reset() {
+        let mut decoder = Decoder::<1024>::new();
+        decoder.push(&stage(0, 0));
+        decoder.push(&stage(0, 2));
+        decoder.push(&stage(0, 3)[..10]);
+        assert![decoder.decode_frame().unwrap().is_some()];
+        assert_eq![decoder.decode_frame().unwrap().unwrap().lost, 1];
+
+        // the partial frame is discarded, and the sequence starts again
+        decoder.reset();
+        assert![decoder.is_empty()];
+        decoder.push(&stage(0, 9));
+        assert_eq![decoder.decode_frame().unwrap().unwrap().lost, 0];
+        assert_eq![decoder.context().lost_sequences(), 0];
+
+        let stats = decoder.stats();
+        assert_eq![stats.frames, 3];
+        assert_eq![stats.skipped_bytes, 10];
+        assert_eq![stats.lost_sequences, 1];
+    }
+
+    #[test]
+    fn stats() {
+        let mut decoder = Decoder::<1024>::new();
+        let mut bad = stage(0, 2);
+        bad[2] ^= 0xFF;
+        decoder.push(b"junk");
+        decoder.push(&stage(0, 0));
+        decoder.push(b"AA4");
+        decoder.push(&bad);
+        decoder.push(&stage(0, 5));
+        while decoder.decode_frame().transpose().is_some() {}
+
+        let stats = decoder.stats();
+        assert_eq![stats.frames, 2];
+        assert_eq![stats.skipped_bytes, 4 + 3 + 16];
+        assert_eq![stats.overrun_bytes, 0];
+        assert_eq![stats.invalid_lengths, 1];
+        assert_eq![stats.invalid_checksums, 1];
+        // the frames 1 to 4 never arrived
+        assert_eq![stats.lost_sequences, 4];
+        assert_eq![decoder.context().lost_sequences(), 4];
+    }
+
+    #[test]
+    fn lost_sequences() {
+        let mut context = Context::new();
+        let header = |seqnum| FrameHeader {
+            protocol: 4,
+            checksum: 0,
+            len: 1,
+            time_low: 0,
+            subsec: 0,
+            seqnum,
+            datatype: DataType::SleepStage,
+        };
+        let lost: Vec<u8> = [254, 255, 0, 3, 3, 2]
+            .into_iter()
+            .map(|seqnum| context.update(header(seqnum), &[]).lost)
+            .collect();
+        assert_eq![lost, [0, 0, 0, 2, 0, 254]];
+        assert_eq![context.lost_sequences(), 2 + 254];
+        assert_eq![context.duplicate_sequences(), 1];
+        assert_eq![context.protocol(), 4];
+    }
+
+    #[test]
+    fn time_reconstruction() {
+        let mut decoder = Decoder::<1024>::new();
+        let time = 0x6400_00FF_u32;
+        let mut decode = |time_low: u32, datatype, data: &[u8]| {
+            decoder.push(&encode(4, time_low, 0, datatype, data));
+            let msg = decoder.decode().unwrap().unwrap();
+            (msg.time, msg.version)
+        };
+
+        // before the first timestamp
+        assert_eq![decode(0x12, DataType::SleepStage, &[0; 4]), (0, 0)];
+
+        let timestamp = time.to_le_bytes();
+        assert_eq![decode(0xFF, DataType::ZeoTimestamp, &timestamp), (time, 0)];
+        assert_eq![decode(0xFF, DataType::Version, &[3, 0, 0, 0]), (time, 3)];
+        // the low byte wraps around in both directions
+        assert_eq![decode(0x00, DataType::SleepStage, &[0; 4]), (time + 1, 3)];
+        assert_eq![decode(0xFE, DataType::SleepStage, &[0; 4]), (time - 1, 3)];
+        // a low byte too far away is ignored
+        assert_eq![decode(0x80, DataType::SleepStage, &[0; 4]), (time, 3)];
+    }
+
+    #[test]
+    fn unsupported_protocol() {
+        let mut decoder = Decoder::<1024>::new();
+        let old = encode(3, 0, 0, DataType::SleepStage, &[1, 0, 0, 0]);
+        decoder.push(&old);
+        decoder.push(&encode(4, 0, 1, DataType::SleepStage, &[2, 0, 0, 0]));
+
+        assert_eq![decoder.decode_frame(), Err(Error::UnsupportedProtocol(3))];
+        let msg = decoder.decode().unwrap().unwrap();
+        assert_eq![msg.protocol, 4];
+        assert_eq![msg.decode(), Ok(Data::SleepStage(SleepStages::Rem))];
+        assert![decoder.is_empty()];
+
+        let stats = decoder.stats();
+        assert_eq![stats.frames, 1];
+        assert_eq![stats.unsupported_protocols, 1];
+        assert_eq![stats.skipped_bytes, old.len() as u64];
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let mut decoder = Decoder::<1024>::new();
+        let stage = [1, 0, 0, 0];
+        for (seqnum, version) in [(0, 2_u32), (2, 3)] {
+            decoder.push(&encode(
+                4,
+                0,
+                seqnum,
+                DataType::Version,
+                &version.to_le_bytes(),
+            ));
+            decoder.push(&encode(4, 0, seqnum + 1, DataType::SleepStage, &stage));
+        }
+
+        let version = decoder.decode().unwrap().unwrap();
+        assert_eq![version.decode(), Ok(Data::Version(2))];
+        let msg = decoder.decode().unwrap().unwrap();
+        assert_eq![msg.version, 2];
+        assert_eq![msg.decode(), Err(Error::UnsupportedVersion(2))];
+
+        let version = decoder.decode().unwrap().unwrap();
+        assert_eq![version.decode(), Ok(Data::Version(3))];
+        let msg = decoder.decode().unwrap().unwrap();
+        assert_eq![msg.decode(), Ok(Data::SleepStage(SleepStages::Awake))];
+        assert_eq![decoder.stats().frames, 4];
+    }
+}
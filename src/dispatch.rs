@@ -0,0 +1,192 @@
+// rawzeo::dispatch
+//
+//! Dispatching of the decoded frames to the registered handlers.
+//
+
+use alloc::{boxed::Box, vec::Vec};
+
+use crate::{Data, DataType, Decoder, Error, Frame};
+
+/// A handler of the decoded frames.
+type FrameHandler<'h> = Box<dyn FnMut(&Frame, &Data) + 'h>;
+
+/// A handler of the sequence gaps.
+type GapHandler<'h> = Box<dyn FnMut(&Frame, u8) + 'h>;
+
+/// A handler of the decoding errors.
+type ErrorHandler<'h> = Box<dyn FnMut(&Error) + 'h>;
+
+/// Dispatches the decoded frames to the registered handlers.
+///
+/// Each frame handler receives the frame, including its full timestamp and
+/// the protocol version, and its decoded data. It can be registered for a
+/// single [`DataType`] or for all of them.
+///
+/// The gap handlers receive the first frame after a sequence gap, and the
+/// number of lost sequences. The error handlers receive every decoding error.
+#[derive(Default)]
+pub struct Dispatcher<'h> {
+    frame_handlers: Vec<(Option<DataType>, FrameHandler<'h>)>,
+    gap_handlers: Vec<GapHandler<'h>>,
+    error_handlers: Vec<ErrorHandler<'h>>,
+}
+
+impl<'h> Dispatcher<'h> {
+    /// Returns a new dispatcher without handlers.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a `handler` for the frames of the given `datatype`.
+    pub fn on<F>(&mut self, datatype: DataType, handler: F) -> &mut Self
+    where
+        F: FnMut(&Frame, &Data) + 'h,
+    {
+        self.frame_handlers
+            .push((Some(datatype), Box::new(handler)));
+        self
+    }
+
+    /// Registers a `handler` for all the frames.
+    pub fn on_frame<F>(&mut self, handler: F) -> &mut Self
+    where
+        F: FnMut(&Frame, &Data) + 'h,
+    {
+        self.frame_handlers.push((None, Box::new(handler)));
+        self
+    }
+
+    /// Registers a `handler` for the sequence gaps.
+    pub fn on_sequence_gap<F>(&mut self, handler: F) -> &mut Self
+    where
+        F: FnMut(&Frame, u8) + 'h,
+    {
+        self.gap_handlers.push(Box::new(handler));
+        self
+    }
+
+    /// Registers a `handler` for the decoding errors.
+    pub fn on_error<F>(&mut self, handler: F) -> &mut Self
+    where
+        F: FnMut(&Error) + 'h,
+    {
+        self.error_handlers.push(Box::new(handler));
+        self
+    }
+
+    /// Dispatches a decoded `frame` to the handlers.
+    ///
+    /// The data is only decoded if there's any handler for its datatype.
+    pub fn dispatch(&mut self, frame: &Frame) {
+        if frame.lost > 0 {
+            for handler in &mut self.gap_handlers {
+                handler(frame, frame.lost);
+            }
+        }
+
+        let datatype = frame.header.datatype;
+        let wanted = |dt: &Option<DataType>| dt.map_or(true, |dt| dt == datatype);
+        if !self.frame_handlers.iter().any(|(dt, _)| wanted(dt)) {
+            return;
+        }
+        match frame.decode() {
+            Ok(data) => {
+                for (_, handler) in self.frame_handlers.iter_mut().filter(|(dt, _)| wanted(dt)) {
+                    handler(frame, &data);
+                }
+            }
+            Err(e) => self.dispatch_error(&e),
+        }
+    }
+
+    /// Dispatches a decoding `error` to the handlers.
+    pub fn dispatch_error(&mut self, error: &Error) {
+        for handler in &mut self.error_handlers {
+            handler(error);
+        }
+    }
+
+    /// Decodes all the frames buffered in the `decoder`,
+    /// and dispatches them to the handlers.
+    ///
+    /// Returns the number of frames dispatched.
+    pub fn run<const CAP: usize>(&mut self, decoder: &mut Decoder<CAP>) -> usize {
+        let mut count = 0;
+        loop {
+            match decoder.decode_frame() {
+                Ok(Some(frame)) => {
+                    self.dispatch(&frame);
+                    count += 1;
+                }
+                Ok(None) => return count,
+                Err(e) => self.dispatch_error(&e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{frame::encode, SleepStages};
+    use alloc::vec;
+
+    #[test]
+    fn dispatch() {
+        let mut decoder = Decoder::<1024>::new();
+        decoder.push(&encode(4, 0, 0, DataType::SleepStage, &[4, 0, 0, 0]));
+        decoder.push(&encode(4, 0, 1, DataType::Sqi, &[25, 0, 0, 0]));
+        let mut bad = encode(4, 0, 2, DataType::Sqi, &[25, 0, 0, 0]);
+        bad[2] ^= 0xFF;
+        decoder.push(&bad);
+        decoder.push(&encode(4, 0, 4, DataType::SleepStage, &[]));
+        decoder.push(&encode(4, 0, 5, DataType::SleepStage, &[1, 0, 0, 0]));
+
+        let (mut stages, mut all, mut gaps, mut errors) = (vec![], vec![], vec![], vec![]);
+        let mut dispatcher = Dispatcher::new();
+        dispatcher
+            .on(DataType::SleepStage, |frame, data| {
+                stages.push((frame.header.seqnum, *data))
+            })
+            .on_frame(|frame, _| all.push(frame.header.seqnum))
+            .on_sequence_gap(|frame, lost| gaps.push((frame.header.seqnum, lost)))
+            .on_error(|error| errors.push(*error));
+        assert_eq![dispatcher.run(&mut decoder), 4];
+        drop(dispatcher);
+
+        assert_eq![
+            stages,
+            [
+                (0, Data::SleepStage(SleepStages::Deep)),
+                (5, Data::SleepStage(SleepStages::Awake))
+            ]
+        ];
+        assert_eq![all, [0, 1, 5]];
+        assert_eq![gaps, [(4, 2)]];
+        assert_eq![
+            errors,
+            [
+                Error::InvalidChecksum,
+                Error::NotEnoughData(DataType::SleepStage)
+            ]
+        ];
+    }
+
+    #[test]
+    fn undecoded() {
+        let mut decoder = Decoder::<1024>::new();
+        decoder.push(&encode(4, 0, 0, DataType::Sqi, &[]));
+        decoder.push(&encode(4, 0, 1, DataType::SleepStage, &[2, 0, 0, 0]));
+
+        // the data without handlers is not decoded, so it can't fail
+        let mut errors = 0;
+        let mut stages = 0;
+        let mut dispatcher = Dispatcher::new();
+        dispatcher
+            .on(DataType::SleepStage, |_, _| stages += 1)
+            .on_error(|_| errors += 1);
+        assert_eq![dispatcher.run(&mut decoder), 2];
+        drop(dispatcher);
+        assert_eq![(stages, errors), (1, 0)];
+    }
+}
@@ -0,0 +1,270 @@
+// rawzeo::edf
+//
+//! Export of the waveform to EDF+ files.
+//
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use crate::{waveform_uv, Data, WAVEFORM_HZ};
+
+/// The length of the fixed part of the header, and of each signal header.
+const HEADER_LEN: usize = 256;
+
+/// The number of signals: the waveform, and the annotations.
+const SIGNALS: usize = 2;
+
+/// The offset of the number of data records in the header.
+const RECORDS_OFFSET: u64 = 236;
+
+/// The number of 2-byte samples of the annotations signal in each record.
+const ANNOTATION_SAMPLES: usize = 60;
+
+/// The abbreviated names of the months, as used in the EDF+ start date.
+const MONTHS: [&str; 12] = [
+    "JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC",
+];
+
+/// Exports the waveform to an EDF+ file, so that it can be analyzed with
+/// the usual sleep and EEG tools.
+///
+/// Each data record holds one second of the waveform, in microvolts,
+/// and an annotations signal with the onset of the record, so that the
+/// gaps left by lost frames are kept (EDF+D). The sleep stages and the
+/// events are annotated in the next record written.
+///
+/// The number of data records in the header is updated on each flush,
+/// so the file is complete once flushed. The data with an unset time,
+/// received before the first timestamp, is not exported.
+#[derive(Debug)]
+pub struct EdfExporter<W: Write + Seek> {
+    out: W,
+    // the unix time of the first data record
+    start: Option<u32>,
+    // the earliest onset of the next data record, in seconds
+    next_onset: u32,
+    records: u64,
+    // the pending annotations, with their unix time
+    annotations: Vec<(u32, String)>,
+}
+
+impl EdfExporter<BufWriter<File>> {
+    /// Creates the EDF file at `path`.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write + Seek> EdfExporter<W> {
+    /// Returns a new exporter over `out`.
+    ///
+    /// The header is written along with the first data record,
+    /// since it contains the start time of the recording.
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            start: None,
+            next_onset: 0,
+            records: 0,
+            annotations: vec![],
+        }
+    }
+
+    /// Returns the number of data records written.
+    pub fn records(&self) -> u64 {
+        self.records
+    }
+
+    /// Writes the `data` received at the given unix `time` in seconds.
+    pub fn write(&mut self, time: u32, data: &Data) -> io::Result<()> {
+        if time <= 1 {
+            return Ok(());
+        }
+        match data {
+            Data::Waveform(samples) => self.write_record(time, samples)?,
+            Data::SleepStage(stage) => {
+                self.annotations
+                    .push((time, format!["Sleep stage {stage}"]));
+            }
+            Data::Event(event) => self.annotations.push((time, event.to_string())),
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// Updates the number of data records in the header, and flushes the output.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.start.is_some() {
+            self.out.seek(SeekFrom::Start(RECORDS_OFFSET))?;
+            self.out.write_all(&field(&self.records.to_string(), 8))?;
+            self.out.seek(SeekFrom::End(0))?;
+        }
+        self.out.flush()
+    }
+
+    /// Writes a data record with the waveform `samples` received at `time`.
+    fn write_record(&mut self, time: u32, samples: &[i16]) -> io::Result<()> {
+        let start = match self.start {
+            Some(start) => start,
+            None => {
+                self.write_header(time)?;
+                self.start = Some(time);
+                time
+            }
+        };
+        // the records can't overlap, even if the clock goes backwards
+        let onset = time.saturating_sub(start).max(self.next_onset);
+        self.next_onset = onset + 1;
+
+        for sample in samples {
+            self.out.write_all(&sample.to_le_bytes())?;
+        }
+        let mut tals = format!["+{onset}\x14\x14\0"].into_bytes();
+        let mut written = 0;
+        for (time, text) in &self.annotations {
+            let onset = *time as i64 - start as i64;
+            let tal = format!["{onset:+}\x14{text}\x14\0"];
+            if tals.len() + tal.len() > ANNOTATION_SAMPLES * 2 {
+                break;
+            }
+            tals.extend(tal.as_bytes());
+            written += 1;
+        }
+        self.annotations.drain(..written);
+        tals.resize(ANNOTATION_SAMPLES * 2, 0);
+        self.out.write_all(&tals)?;
+        self.records += 1;
+        Ok(())
+    }
+
+    /// Writes the header of a recording starting at the unix `time`.
+    fn write_header(&mut self, time: u32) -> io::Result<()> {
+        let (year, month, day) = civil_date(time / 86400);
+        let secs = time % 86400;
+        let mut header = vec![];
+        header.extend(field("0", 8));
+        header.extend(field("X X X X", 80));
+        let recording = format![
+            "Startdate {day:02}-{}-{year} X X rawzeo",
+            MONTHS[month as usize - 1]
+        ];
+        header.extend(field(&recording, 80));
+        header.extend(field(&format!["{day:02}.{month:02}.{:02}", year % 100], 8));
+        let start_time = format!["{:02}.{:02}.{:02}", secs / 3600, secs / 60 % 60, secs % 60];
+        header.extend(field(&start_time, 8));
+        header.extend(field(&(HEADER_LEN * (SIGNALS + 1)).to_string(), 8));
+        header.extend(field("EDF+D", 44));
+        // unknown until the first flush
+        header.extend(field("-1", 8));
+        header.extend(field("1", 8));
+        header.extend(field(&SIGNALS.to_string(), 4));
+
+        let physical_min = format!["{:.3}", waveform_uv(i16::MIN)];
+        let physical_max = format!["{:.3}", waveform_uv(i16::MAX)];
+        let samples = WAVEFORM_HZ.to_string();
+        let annotation_samples = ANNOTATION_SAMPLES.to_string();
+        for (fields, len) in [
+            (["EEG", "EDF Annotations"], 16),
+            (["Zeo headband", ""], 80),
+            (["uV", ""], 8),
+            ([&physical_min, "-1"], 8),
+            ([&physical_max, "1"], 8),
+            (["-32768", "-32768"], 8),
+            (["32767", "32767"], 8),
+            (["", ""], 80),
+            ([&samples, &annotation_samples], 8),
+            (["", ""], 32),
+        ] {
+            for value in fields {
+                header.extend(field(value, len));
+            }
+        }
+        self.out.write_all(&header)
+    }
+}
+
+/// Returns the `value` as a header field of `len` bytes, padded with spaces.
+fn field(value: &str, len: usize) -> Vec<u8> {
+    let mut field = value.as_bytes()[..value.len().min(len)].to_vec();
+    field.resize(len, b' ');
+    field
+}
+
+/// Returns the year, month and day of the given days since the unix epoch.
+fn civil_date(days: u32) -> (u32, u32, u32) {
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as u32;
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EventType, SleepStages};
+    use std::io::Cursor;
+
+    #[test]
+    fn records() {
+        let mut edf = EdfExporter::new(Cursor::new(vec![]));
+        // 2023-02-01 22:00:00
+        let t = 1_675_288_800;
+        let mut samples = [0; 128];
+        samples[1] = -1000;
+        for (time, data) in [
+            (0, Data::Waveform(samples)),
+            (t - 1, Data::Event(EventType::NightStart)),
+            (t, Data::Waveform(samples)),
+            (t, Data::SleepStage(SleepStages::Light)),
+            // a second lost, and then the clock going backwards
+            (t + 2, Data::Waveform(samples)),
+            (t + 2, Data::Waveform(samples)),
+        ] {
+            edf.write(time, &data).unwrap();
+        }
+        edf.flush().unwrap();
+        assert_eq![edf.records(), 3];
+
+        let out = edf.out.into_inner();
+        let text = |range: std::ops::Range<usize>| std::str::from_utf8(&out[range]).unwrap();
+        assert_eq![text(0..8), "0       "];
+        assert![text(88..168).starts_with("Startdate 01-FEB-2023 X X rawzeo ")];
+        assert_eq![text(168..184), "01.02.2322.00.00"];
+        assert_eq![text(184..192), "768     "];
+        assert![text(192..236).starts_with("EDF+D ")];
+        assert_eq![text(236..256), "3       1       2   "];
+        assert_eq![text(256..288), "EEG             EDF Annotations "];
+        assert_eq![text(256 + 104 * 2..256 + 112 * 2), "-315.000-1      "];
+        assert_eq![text(256 + 216 * 2..256 + 224 * 2), "128     60      "];
+
+        let record_len = 128 * 2 + ANNOTATION_SAMPLES * 2;
+        assert_eq![out.len(), 768 + 3 * record_len];
+        let record = |i: usize| &out[768 + i * record_len..768 + (i + 1) * record_len];
+        assert_eq![record(0)[2..4], (-1000_i16).to_le_bytes()];
+        let annotations = |i: usize| {
+            let tals = &record(i)[256..];
+            let end = tals.iter().rposition(|b| *b != 0).unwrap();
+            std::str::from_utf8(&tals[..=end]).unwrap().to_string()
+        };
+        assert_eq![annotations(0), "+0\x14\x14\0-1\x14NightStart\x14"];
+        assert_eq![annotations(1), "+2\x14\x14\0+0\x14Sleep stage Light\x14"];
+        assert_eq![annotations(2), "+3\x14\x14"];
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq![civil_date(0), (1970, 1, 1)];
+        assert_eq![civil_date(11_016), (2000, 2, 29)];
+        assert_eq![civil_date(19_389), (2023, 2, 1)];
+    }
+}
@@ -0,0 +1,47 @@
+// rawzeo::error
+//
+//! Error types.
+//
+
+use core::fmt;
+
+use crate::DataType;
+
+/// The errors that may occur while decoding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The message length doesn't match its inverse, or is out of range.
+    InvalidLength,
+
+    /// The checksum doesn't match the datatype and data bytes.
+    InvalidChecksum,
+
+    /// The datatype is not a known one.
+    InvalidDatatype(u8),
+
+    /// There are not enough data bytes for the datatype.
+    NotEnoughData(DataType),
+
+    /// The protocol version of the frame header (`n`) is not supported.
+    UnsupportedProtocol(u8),
+
+    /// The version of the raw data output is not supported.
+    UnsupportedVersion(u32),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Error::*;
+        match self {
+            InvalidLength => f.write_str("Invalid message length."),
+            InvalidChecksum => f.write_str("Invalid checksum."),
+            InvalidDatatype(b) => write!(f, "Bad datatype: 0x{b:02X}."),
+            NotEnoughData(t) => write!(f, "Not enough data bytes for {t}."),
+            UnsupportedProtocol(n) => write!(f, "Unsupported protocol version: {n}."),
+            UnsupportedVersion(v) => write!(f, "Unsupported raw data output version: {v}."),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
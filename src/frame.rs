@@ -0,0 +1,403 @@
+// rawzeo::frame
+//
+//! Zero-copy parsing of the frames from the serial byte stream.
+//
+
+use core::fmt;
+
+use log::{debug, trace};
+
+use crate::{Context, Data, DataType, Error};
+
+/// The length of the frame header, including the datatype byte.
+pub const HEADER_LEN: usize = 12;
+
+/// The maximum length of the data bytes of a frame.
+pub const MAX_DATA_LEN: usize = 256;
+
+/// The maximum length of a complete frame.
+pub const MAX_FRAME_LEN: usize = HEADER_LEN + MAX_DATA_LEN;
+
+/// The protocol versions of the frame header (`n`) that can be decoded.
+///
+/// Version 4 is the only one known: no other version has been seen or
+/// documented, so any other one is assumed to have a different layout.
+/// Frames with any other version are skipped
+/// with an [`Error::UnsupportedProtocol`].
+pub const PROTOCOL_VERSIONS: &[u8] = &[4];
+
+/// The minimum number of bytes needed before trying to parse a frame.
+pub(crate) const MIN_FRAME_LEN: usize = 16;
+
+/// The header of a frame: `AncllLLTttsi`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameHeader {
+    /// The protocol version (`n`).
+    pub protocol: u8,
+
+    /// The checksum of the datatype and data bytes (`c`).
+    pub checksum: u8,
+
+    /// The length of the datatype plus the data bytes (`ll`).
+    pub len: u16,
+
+    /// The lower 8 bits of Zeo's unix time (`T`).
+    pub time_low: u8,
+
+    /// The sub-second (`tt`).
+    pub subsec: u16,
+
+    /// The sequence number (`s`).
+    pub seqnum: u8,
+
+    /// The type of the data (`i`).
+    pub datatype: DataType,
+}
+
+impl FrameHeader {
+    /// Returns the length of the data bytes.
+    ///
+    /// The length includes the datatype byte, so a length of 0 is invalid,
+    /// and has no data bytes.
+    pub fn data_len(&self) -> usize {
+        (self.len as usize).saturating_sub(1)
+    }
+
+    /// Returns the length of the complete frame.
+    pub fn frame_len(&self) -> usize {
+        HEADER_LEN + self.data_len()
+    }
+
+    /// Returns the header encoded as it was received.
+    pub fn to_bytes(&self) -> [u8; HEADER_LEN] {
+        let [l0, l1] = self.len.to_le_bytes();
+        let [i0, i1] = (!self.len).to_le_bytes();
+        let [t0, t1] = self.subsec.to_le_bytes();
+        [
+            b'A',
+            b'0' + self.protocol,
+            self.checksum,
+            l0,
+            l1,
+            i0,
+            i1,
+            self.time_low,
+            t0,
+            t1,
+            self.seqnum,
+            self.datatype.into(),
+        ]
+    }
+}
+
+/// A frame borrowing its data bytes from the input buffer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Frame<'a> {
+    /// The header of the frame.
+    pub header: FrameHeader,
+
+    /// The reconstructed unix time, in seconds.
+    pub time: u32,
+
+    /// The version of the raw data output.
+    pub version: u32,
+
+    /// The number of sequences lost right before this frame.
+    pub lost: u8,
+
+    /// The data bytes.
+    pub data: &'a [u8],
+}
+
+impl Frame<'_> {
+    /// Decodes the data bytes, according to the version of the raw data output.
+    pub fn decode(&self) -> Result<Data, Error> {
+        Data::decode_versioned(self.header.datatype, self.version, self.data)
+    }
+}
+
+/// Returns the bytes of a frame with the given fields, and a valid checksum.
+#[cfg(test)]
+pub(crate) fn encode(
+    protocol: u8,
+    time: u32,
+    seqnum: u8,
+    datatype: DataType,
+    data: &[u8],
+) -> std::vec::Vec<u8> {
+    let dtype = u8::from(datatype);
+    let len = data.len() as u16 + 1;
+    let mut bytes = std::vec![b'A', b'0' + protocol];
+    bytes.push(data.iter().fold(dtype, |sum, b| sum.wrapping_add(*b)));
+    bytes.extend_from_slice(&len.to_le_bytes());
+    bytes.extend_from_slice(&(!len).to_le_bytes());
+    bytes.push(time as u8);
+    bytes.extend_from_slice(&0_u16.to_le_bytes());
+    bytes.push(seqnum);
+    bytes.push(dtype);
+    bytes.extend_from_slice(data);
+    bytes
+}
+
+/// The result of scanning some bytes for the next frame.
+pub(crate) enum Scan {
+    /// There are not enough bytes for a complete frame,
+    /// after skipping the bytes preceding a possible frame start.
+    Incomplete { skip: usize },
+
+    /// The frame is invalid, and the bytes to skip before trying again.
+    Invalid { skip: usize, error: Error },
+
+    /// A valid frame starting after skipping some bytes.
+    Frame { skip: usize, header: FrameHeader },
+}
+
+/// Scans the `bytes` for the next frame.
+pub(crate) fn scan(bytes: &[u8]) -> Scan {
+    // Check if data length is at least 16 bytes (minimum length of a valid packet)
+    if bytes.len() < MIN_FRAME_LEN {
+        return Scan::Incomplete { skip: 0 };
+    }
+    trace!("» PARSE {}", HexBytes(bytes));
+
+    // 1. Parse message start and protocol version (+2 = 2 bytes)
+    let start = |w: &[u8]| w[0] == b'A' && w[1].is_ascii_digit();
+    let skip = match bytes.windows(2).position(start) {
+        Some(i) => i,
+        // keep the last byte in case it's the start of a message
+        None => {
+            return Scan::Incomplete {
+                skip: bytes.len() - 1,
+            }
+        }
+    };
+    let b = &bytes[skip..];
+    let protocol = b[1] - b'0';
+
+    // make sure there's enough bytes left.
+    //
+    // Otherwise the buffer will be filled with more bytes and we'll try again.
+    if b.len() < MIN_FRAME_LEN {
+        trace!("> (not enough bytes left: {} )", b.len());
+        return Scan::Incomplete { skip };
+    }
+
+    debug!("> protocol: {protocol}");
+
+    // 2. Parse the checksum byte (+1 = 3 bytes)
+    let checksum = b[2];
+    debug!("> checksum: 0x{checksum:02X} ({checksum})");
+
+    // 3. Parse message length bytes (+4 = 7 bytes)
+    let dl = u16::from_le_bytes([b[3], b[4]]);
+    let inv_dl = u16::from_le_bytes([b[5], b[6]]);
+    debug!("> dl:{dl} inv:{inv_dl}→(inv:{})", !inv_dl);
+
+    // Check if message lengths match,
+    // otherwise look for the start of the next message.
+    if dl != !inv_dl || dl == 0 || dl as usize - 1 > MAX_DATA_LEN {
+        return Scan::Invalid {
+            skip: skip + 2,
+            error: Error::InvalidLength,
+        };
+    }
+    let datalen = dl as usize - 1;
+
+    // Make sure the rest of the message has already been received.
+    if b.len() < HEADER_LEN + datalen {
+        trace!("> (not enough bytes left for the data: {} )", b.len());
+        return Scan::Incomplete { skip };
+    }
+
+    // 4. Parse timestamp bytes (+3 = 10 bytes)
+    //
+    // timestamp low byte
+    let tt_lb = b[7];
+    // timestamp sub-seconds
+    let tt_ss = u16::from_le_bytes([b[8], b[9]]);
+    // timestamp floating point subsec
+    let tt_fss = (tt_ss.saturating_sub(1)) as f32 / 15.0;
+    debug!("> tt_lb: 0x{tt_lb:02X} ({tt_lb}), tt_ss:({tt_ss})({tt_fss:.02})");
+
+    // 5. Parse sequence number byte (+ 1 = 11 bytes)
+    let seqnum = b[10];
+    debug!("> seqnum: {seqnum}");
+
+    // 6. Parse data type byte (+1 = 12 bytes)
+    let dtype = b[11];
+    let datatype = DataType::from(dtype);
+    debug!("> datatype: {datatype}");
+
+    // 7. Parse data bytes
+    let data = &b[HEADER_LEN..HEADER_LEN + datalen];
+    trace!("> DATA: {}", HexBytes(data));
+
+    // 8. Verify checksum
+    let sum = data.iter().fold(dtype, |sum, b| sum.wrapping_add(*b));
+    if sum != checksum {
+        return Scan::Invalid {
+            skip: skip + HEADER_LEN + datalen,
+            error: Error::InvalidChecksum,
+        };
+    }
+
+    // 9. Verify the protocol version, once the frame is known to be valid
+    if !PROTOCOL_VERSIONS.contains(&protocol) {
+        return Scan::Invalid {
+            skip: skip + HEADER_LEN + datalen,
+            error: Error::UnsupportedProtocol(protocol),
+        };
+    }
+
+    Scan::Frame {
+        skip,
+        header: FrameHeader {
+            protocol,
+            checksum,
+            len: dl,
+            time_low: tt_lb,
+            subsec: tt_ss,
+            seqnum,
+            datatype,
+        },
+    }
+}
+
+/// Returns an iterator over the frames of a complete byte stream.
+///
+/// Useful for processing archived captures without copying.
+pub fn frames(bytes: &[u8]) -> Frames<'_> {
+    Frames {
+        bytes,
+        context: Context::new(),
+    }
+}
+
+/// An iterator over the frames of a complete byte stream.
+///
+/// It yields an error for each invalid frame found, and skips over any
+/// bytes that don't belong to a frame.
+///
+/// It's created by the [`frames`] function.
+#[derive(Clone, Debug)]
+pub struct Frames<'a> {
+    bytes: &'a [u8],
+    context: Context,
+}
+
+impl<'a> Frames<'a> {
+    /// Returns the bytes not yet iterated over.
+    pub fn remaining(&self) -> &'a [u8] {
+        self.bytes
+    }
+}
+
+impl<'a> Iterator for Frames<'a> {
+    type Item = Result<Frame<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match scan(self.bytes) {
+            Scan::Incomplete { skip } => {
+                self.bytes = &self.bytes[skip..];
+                None
+            }
+            Scan::Invalid { skip, error } => {
+                self.bytes = &self.bytes[skip..];
+                Some(Err(error))
+            }
+            Scan::Frame { skip, header } => {
+                let frame = &self.bytes[skip..skip + header.frame_len()];
+                self.bytes = &self.bytes[skip + header.frame_len()..];
+                Some(Ok(self.context.update(header, &frame[HEADER_LEN..])))
+            }
+        }
+    }
+}
+
+/// Displays bytes in hexadecimal, preceded by their length.
+struct HexBytes<'a>(&'a [u8]);
+
+impl fmt::Display for HexBytes<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{} B]:", self.0.len())?;
+        for b in self.0 {
+            write!(f, " {b:02X}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn incomplete() {
+        let frame = encode(4, 0, 0, DataType::Waveform, &[0; 256]);
+        assert![matches![scan(&frame[..15]), Scan::Incomplete { skip: 0 }]];
+        // the data bytes are still being received
+        assert![matches![scan(&frame[..100]), Scan::Incomplete { skip: 0 }]];
+        // the last byte may start a frame
+        assert![matches![
+            scan(b"no frame here..A"),
+            Scan::Incomplete { skip: 15 }
+        ]];
+
+        let mut bytes = b"xyz".to_vec();
+        bytes.extend_from_slice(&frame[..20]);
+        assert![matches![scan(&bytes), Scan::Incomplete { skip: 3 }]];
+    }
+
+    #[test]
+    fn header() {
+        let frame = encode(4, 0x1234, 7, DataType::Impedance, &[1, 2, 3, 4]);
+        let header = match scan(&frame) {
+            Scan::Frame { skip: 0, header } => header,
+            _ => panic!("the frame wasn't scanned"),
+        };
+        assert_eq![header.protocol, 4];
+        assert_eq![header.checksum, 0x97 + 1 + 2 + 3 + 4];
+        assert_eq![header.len, 5];
+        assert_eq![header.time_low, 0x34];
+        assert_eq![header.seqnum, 7];
+        assert_eq![header.datatype, DataType::Impedance];
+        assert_eq![header.frame_len(), frame.len()];
+
+        let empty = FrameHeader { len: 0, ..header };
+        assert_eq![empty.data_len(), 0];
+        assert_eq![empty.frame_len(), HEADER_LEN];
+    }
+
+    #[test]
+    fn frames_remaining() {
+        let time = 1_675_288_800_u32;
+        let mut bytes = b"xx".to_vec();
+        bytes.extend(encode(
+            4,
+            time,
+            0,
+            DataType::ZeoTimestamp,
+            &time.to_le_bytes(),
+        ));
+        let mut bad = encode(4, time, 1, DataType::SleepStage, &[1, 0, 0, 0]);
+        bad[12] = 2;
+        bytes.extend(&bad);
+        bytes.extend(encode(4, time, 2, DataType::SleepStage, &[2, 0, 0, 0]));
+        let partial = encode(4, time, 3, DataType::SleepStage, &[4, 0, 0, 0]);
+        bytes.extend(&partial[..14]);
+
+        let mut frames = frames(&bytes);
+        let frame = frames.next().unwrap().unwrap();
+        assert_eq![frame.decode(), Ok(Data::ZeoTimestamp(time))];
+        assert_eq![frames.next(), Some(Err(Error::InvalidChecksum))];
+
+        // the data is borrowed from the input, with the context updated
+        let frame = frames.next().unwrap().unwrap();
+        assert_eq![frame.data.as_ptr(), bytes[bytes.len() - 18..].as_ptr()];
+        assert_eq![frame.time, time];
+        assert_eq![frame.lost, 1];
+
+        assert_eq![frames.next(), None];
+        assert_eq![frames.remaining(), &partial[..14]];
+    }
+}
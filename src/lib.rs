@@ -10,7 +10,8 @@ Data is sent Least Significant Byte first.
 The serial protocol is: `AncllLLTttsidddd`, where:
 
 * A  is a character starting the message
-* n  is the protocol "version", ie "4"
+* n  is the protocol "version", ie "4", sent as an ASCII digit.
+  The supported ones are listed in [`PROTOCOL_VERSIONS`].
 * c  is a one byte checksum formed by summing the identifier byte and all
   the data bytes
 * ll is a two byte message length sent LSB first. This length includes the
@@ -36,13 +37,60 @@ The serial protocol is: `AncllLLTttsidddd`, where:
     clippy::blanket_clippy_restriction_lints,
     clippy::pattern_type_mismatch
 )]
-// #![cfg_attr(not(feature = "std"), no_std)]
+#![cfg_attr(not(feature = "std"), no_std)]
 #![forbid(unsafe_code)]
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+#[cfg(all(test, not(feature = "std")))]
+extern crate std;
+
 use core::fmt;
 
+#[cfg(feature = "std")]
+mod csv;
+mod data;
+mod decoder;
+#[cfg(feature = "alloc")]
+mod dispatch;
+#[cfg(feature = "std")]
+mod edf;
+mod error;
+mod frame;
+#[cfg(feature = "std")]
+mod pcap;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+#[cfg(feature = "tokio")]
+mod stream;
+
+#[cfg(feature = "std")]
+pub use csv::CsvExporter;
+#[cfg(feature = "std")]
+pub use data::impedance;
+pub use data::{
+    waveform_uv, Data, FREQUENCY_BINS_LEN, RAW_DATA_VERSIONS, SLEEP_STAGE_SECS, WAVEFORM_HZ,
+    WAVEFORM_LEN,
+};
+pub use decoder::{Context, Decoder, Message, Segment, Stats};
+#[cfg(feature = "alloc")]
+pub use dispatch::Dispatcher;
+#[cfg(feature = "std")]
+pub use edf::EdfExporter;
+pub use error::Error;
+pub use frame::{
+    frames, Frame, FrameHeader, Frames, HEADER_LEN, MAX_DATA_LEN, MAX_FRAME_LEN, PROTOCOL_VERSIONS,
+};
+#[cfg(feature = "std")]
+pub use pcap::{PcapExporter, PcapSegment, PCAP_LINKTYPE};
+#[cfg(feature = "sqlite")]
+pub use sqlite::{Night, Session, Slice, SqliteStore, SQLITE_SCHEMA};
+#[cfg(feature = "tokio")]
+pub use stream::MessageStream;
+
 /// All the types of events the base may send.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 #[repr(u8)]
 pub enum DataType {
     /// An event has occured.
@@ -121,32 +169,47 @@ impl From<u8> for DataType {
         }
     }
 }
+impl From<DataType> for u8 {
+    fn from(t: DataType) -> u8 {
+        use DataType::*;
+        match t {
+            Event => 0x00,
+            SliceEnd => 0x02,
+            Version => 0x03,
+            Waveform => 0x80,
+            FrequencyBins => 0x83,
+            Sqi => 0x84,
+            ZeoTimestamp => 0x8A,
+            Impedance => 0x97,
+            BadSignal => 0x9C,
+            SleepStage => 0x9D,
+            Invalid(b) => b,
+        }
+    }
+}
 
 impl fmt::Display for DataType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use DataType::*;
-        write!(
-            f,
-            "{}",
-            match self {
-                Event => "Event".into(),
-                SliceEnd => "SliceEnd".into(),
-                Version => "Version".into(),
-                Waveform => "Waveform".into(),
-                FrequencyBins => "FrequencyBins".into(),
-                Sqi => "Sqi".into(),
-                ZeoTimestamp => "ZeoTimestamp".into(),
-                Impedance => "Impedance".into(),
-                BadSignal => "BadSignal".into(),
-                SleepStage => "SleepStage".into(),
-                Invalid(b) => format!["Invalid({b})"],
-            }
-        )
+        f.write_str(match self {
+            Event => "Event",
+            SliceEnd => "SliceEnd",
+            Version => "Version",
+            Waveform => "Waveform",
+            FrequencyBins => "FrequencyBins",
+            Sqi => "Sqi",
+            ZeoTimestamp => "ZeoTimestamp",
+            Impedance => "Impedance",
+            BadSignal => "BadSignal",
+            SleepStage => "SleepStage",
+            Invalid(b) => return write!(f, "Invalid({b})"),
+        })
     }
 }
 
 /// All the types of events that may be fired.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 #[repr(u8)]
 pub enum EventType {
     /// User's night has begun.
@@ -186,7 +249,7 @@ impl From<u8> for EventType {
             0x05 => NightStart,
             0x07 => SleepOnset,
             0x0E => HeadbandDocked,
-            0x8F => HeadbandUnDocked,
+            0x0F => HeadbandUnDocked,
             0x10 => AlarmOff,
             0x11 => AlarmSnooze,
             0x13 => AlarmPlay,
@@ -196,30 +259,44 @@ impl From<u8> for EventType {
         }
     }
 }
+impl From<EventType> for u8 {
+    fn from(e: EventType) -> u8 {
+        use EventType::*;
+        match e {
+            NightStart => 0x05,
+            SleepOnset => 0x07,
+            HeadbandDocked => 0x0E,
+            HeadbandUnDocked => 0x0F,
+            AlarmOff => 0x10,
+            AlarmSnooze => 0x11,
+            AlarmPlay => 0x13,
+            NightEnd => 0x15,
+            NewHeadband => 0x24,
+            Invalid(b) => b,
+        }
+    }
+}
 impl fmt::Display for EventType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use EventType::*;
-        write!(
-            f,
-            "{}",
-            match self {
-                NightStart => "NightStart".into(),
-                SleepOnset => "SleepOnset".into(),
-                HeadbandDocked => "HeadbandDocked".into(),
-                HeadbandUnDocked => "HeadbandUnDocked".into(),
-                AlarmOff => "AlarmOff".into(),
-                AlarmSnooze => "AlarmSnooze".into(),
-                AlarmPlay => "AlarmPlay".into(),
-                NightEnd => "NightEnd".into(),
-                NewHeadband => "NewHeadband".into(),
-                Invalid(b) => format!["Invalid({b})"],
-            }
-        )
+        f.write_str(match self {
+            NightStart => "NightStart",
+            SleepOnset => "SleepOnset",
+            HeadbandDocked => "HeadbandDocked",
+            HeadbandUnDocked => "HeadbandUnDocked",
+            AlarmOff => "AlarmOff",
+            AlarmSnooze => "AlarmSnooze",
+            AlarmPlay => "AlarmPlay",
+            NightEnd => "NightEnd",
+            NewHeadband => "NewHeadband",
+            Invalid(b) => return write!(f, "Invalid({b})"),
+        })
     }
 }
 
 /// All the frequency bins.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 #[repr(u8)]
 pub enum FrequencyBins {
     /// Delta (2-4 Hz).
@@ -263,6 +340,21 @@ impl FrequencyBins {
             Invalid(_) => (0, 0),
         }
     }
+
+    /// Returns the name of this frequency bin in `snake_case`.
+    pub fn name(&self) -> &'static str {
+        use FrequencyBins::*;
+        match self {
+            Delta => "delta",
+            Theta => "theta",
+            Alpha => "alpha",
+            BetaMid => "beta_mid",
+            BetaHigh => "beta_high",
+            BetaLow => "beta_low",
+            Gamma => "gamma",
+            Invalid(_) => "invalid",
+        }
+    }
     pub fn is_delta(&self) -> bool {
         matches![self, FrequencyBins::Delta]
     }
@@ -295,28 +387,40 @@ impl From<u8> for FrequencyBins {
         }
     }
 }
+impl From<FrequencyBins> for u8 {
+    fn from(f: FrequencyBins) -> u8 {
+        use FrequencyBins::*;
+        match f {
+            Delta => 0x00,
+            Theta => 0x01,
+            Alpha => 0x02,
+            BetaMid => 0x03,
+            BetaHigh => 0x04,
+            BetaLow => 0x05,
+            Gamma => 0x06,
+            Invalid(b) => b,
+        }
+    }
+}
 impl fmt::Display for FrequencyBins {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use FrequencyBins::*;
-        write!(
-            f,
-            "{}",
-            match self {
-                Delta => "Delta".into(),
-                Theta => "Theta".into(),
-                Alpha => "Alpha".into(),
-                BetaMid => "BetaMid".into(),
-                BetaHigh => "BetaHigh".into(),
-                BetaLow => "BetaLow".into(),
-                Gamma => "Gamma".into(),
-                Invalid(b) => format!["Invalid({b})"],
-            }
-        )
+        f.write_str(match self {
+            Delta => "Delta",
+            Theta => "Theta",
+            Alpha => "Alpha",
+            BetaMid => "BetaMid",
+            BetaHigh => "BetaHigh",
+            BetaLow => "BetaLow",
+            Gamma => "Gamma",
+            Invalid(b) => return write!(f, "Invalid({b})"),
+        })
     }
 }
 
 /// The sleep stages output by the base.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 #[repr(u8)]
 pub enum SleepStages {
     /// Sleeps tage unsure.
@@ -350,41 +454,110 @@ impl From<u8> for SleepStages {
         }
     }
 }
+impl From<SleepStages> for u8 {
+    fn from(s: SleepStages) -> u8 {
+        use SleepStages::*;
+        match s {
+            Undefined => 0x00,
+            Awake => 0x01,
+            Rem => 0x02,
+            Light => 0x03,
+            Deep => 0x04,
+            Invalid(b) => b,
+        }
+    }
+}
 impl fmt::Display for SleepStages {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use SleepStages::*;
-        write!(
-            f,
-            "{}",
-            match self {
-                Undefined => "Undefined".into(),
-                Awake => "Awake".into(),
-                Rem => "Rem".into(),
-                Light => "Light".into(),
-                Deep => "Deep".into(),
-                Invalid(b) => format!["Invalid({b})"],
-            }
-        )
+        f.write_str(match self {
+            Undefined => "Undefined",
+            Awake => "Awake",
+            Rem => "Rem",
+            Light => "Light",
+            Deep => "Deep",
+            Invalid(b) => return write!(f, "Invalid({b})"),
+        })
     }
 }
 
+/// The number of coefficients of the filter used by [`filter60hz_into`].
+pub const FILTER60HZ_LEN: usize = 51;
+
 /// Filters out 60hz noise from a signal.
 /// In practice it is a sinc low pass filter with cutoff frequency of 50hz.
+///
+/// The returned signal is `FILTER60HZ_LEN - 1` samples longer than `a`.
+#[cfg(feature = "alloc")]
+pub fn filter60hz(a: &[f64]) -> alloc::vec::Vec<f64> {
+    let mut c = alloc::vec![0.0; a.len() + FILTER60HZ_LEN - 1];
+    filter60hz_into(a, &mut c);
+    c
+}
+
+/// Filters out 60hz noise from a signal, without allocating.
+///
+/// Writes the filtered signal into `c`, which must be at least
+/// `FILTER60HZ_LEN - 1` samples longer than `a`.
+///
+/// # Panics
+/// Panics if `c` is not long enough.
 // fn filter_60hz<const LEN: >(a: [8; 256]) {
-pub fn filter60hz(a: &[f64]) -> Vec<f64> {
+pub fn filter60hz_into(a: &[f64], c: &mut [f64]) {
     // Filter designed in matlab
-    let filter: [f64; 51] = [
+    let filter: [f64; FILTER60HZ_LEN] = [
         0.0056, 0.0190, 0.0113, -0.0106, 0.0029, 0.0041, -0.0082, 0.0089, -0.0062, 0.0006, 0.0066,
         -0.0129, 0.0157, -0.0127, 0.0035, 0.0102, -0.0244, 0.0336, -0.0323, 0.0168, 0.0136,
         -0.0555, 0.1020, -0.1446, 0.1743, 0.8150, 0.1743, -0.1446, 0.1020, -0.0555, 0.0136, 0.0168,
         -0.0323, 0.0336, -0.0244, 0.0102, 0.0035, -0.0127, 0.0157, -0.0129, 0.0066, 0.0006,
         -0.0062, 0.0089, -0.0082, 0.0041, 0.0029, -0.0106, 0.0113, 0.0190, 0.0056,
     ];
+    convolve(a, &filter, c);
+}
+
+/// The number of coefficients of the filter used by [`filter50hz_into`].
+pub const FILTER50HZ_LEN: usize = 51;
+
+/// Filters out 50hz noise from a signal.
+/// In practice it is a windowed sinc low pass filter with cutoff frequency of 42hz.
+///
+/// The returned signal is `FILTER50HZ_LEN - 1` samples longer than `a`.
+#[cfg(feature = "alloc")]
+pub fn filter50hz(a: &[f64]) -> alloc::vec::Vec<f64> {
+    let mut c = alloc::vec![0.0; a.len() + FILTER50HZ_LEN - 1];
+    filter50hz_into(a, &mut c);
+    c
+}
+
+/// Filters out 50hz noise from a signal, without allocating.
+///
+/// Writes the filtered signal into `c`, which must be at least
+/// `FILTER50HZ_LEN - 1` samples longer than `a`.
+///
+/// # Panics
+/// Panics if `c` is not long enough.
+pub fn filter50hz_into(a: &[f64], c: &mut [f64]) {
+    // Hamming windowed sinc, normalized to unity gain at 0hz
+    let filter: [f64; FILTER50HZ_LEN] = [
+        0.0010, -0.0008, -0.0004, 0.0016, -0.0013, -0.0010, 0.0034, -0.0024, -0.0026, 0.0068,
+        -0.0040, -0.0057, 0.0124, -0.0058, -0.0115, 0.0213, -0.0075, -0.0221, 0.0362, -0.0091,
+        -0.0449, 0.0693, -0.0101, -0.1303, 0.2795, 0.6557, 0.2795, -0.1303, -0.0101, 0.0693,
+        -0.0449, -0.0091, 0.0362, -0.0221, -0.0075, 0.0213, -0.0115, -0.0058, 0.0124, -0.0057,
+        -0.0040, 0.0068, -0.0026, -0.0024, 0.0034, -0.0010, -0.0013, 0.0016, -0.0004, -0.0008,
+        0.0010,
+    ];
+    convolve(a, &filter, c);
+}
+
+/// Convolves the signal `a` with the `filter`, writing the result into `c`.
+fn convolve(a: &[f64], filter: &[f64], c: &mut [f64]) {
     // Convolution math from http://web.archive.org/web/20100528145622/http://www.phys.uu.nl/~haque/computing/WPark_recipes_in_python.html
     let p = a.len();
     let q = filter.len();
     let n = p + q - 1;
-    let mut c = vec![0.0; n];
+    if p == 0 {
+        return;
+    }
     for k in 0..n {
         let mut t = 0.0;
         let lower = k.saturating_sub(q - 1);
@@ -394,7 +567,6 @@ pub fn filter60hz(a: &[f64]) -> Vec<f64> {
         }
         c[k] = t;
     }
-    c
 
     // P = len(A)
     // Q = len(filter)
@@ -409,3 +581,81 @@ pub fn filter60hz(a: &[f64]) -> Vec<f64> {
     //     c.append(t)
     // return c
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Asserts that every byte converts to the enum `T` and back unchanged,
+    /// and that none of the `known` variants decodes as invalid.
+    fn assert_round_trip<T>(known: &[T], is_invalid: fn(&T) -> bool)
+    where
+        T: From<u8> + Copy + PartialEq + fmt::Debug,
+        u8: From<T>,
+    {
+        for b in 0..=u8::MAX {
+            assert_eq![u8::from(T::from(b)), b];
+        }
+        for &v in known {
+            assert_eq![T::from(u8::from(v)), v];
+            assert![!is_invalid(&T::from(u8::from(v))), "{v:?}"];
+        }
+    }
+
+    #[test]
+    fn data_type_round_trip() {
+        use DataType::*;
+        assert_round_trip(
+            &[
+                Event,
+                SliceEnd,
+                Version,
+                Waveform,
+                FrequencyBins,
+                Sqi,
+                ZeoTimestamp,
+                Impedance,
+                BadSignal,
+                SleepStage,
+            ],
+            |t| matches![t, Invalid(_)],
+        );
+    }
+
+    #[test]
+    fn event_type_round_trip() {
+        use EventType::*;
+        assert_round_trip(
+            &[
+                NightStart,
+                SleepOnset,
+                HeadbandDocked,
+                HeadbandUnDocked,
+                AlarmOff,
+                AlarmSnooze,
+                AlarmPlay,
+                NightEnd,
+                NewHeadband,
+            ],
+            |e| matches![e, Invalid(_)],
+        );
+        assert_eq![EventType::from(0x0F), HeadbandUnDocked];
+    }
+
+    #[test]
+    fn frequency_bins_round_trip() {
+        use FrequencyBins::*;
+        assert_round_trip(
+            &[Delta, Theta, Alpha, BetaMid, BetaHigh, BetaLow, Gamma],
+            |f| matches![f, Invalid(_)],
+        );
+    }
+
+    #[test]
+    fn sleep_stages_round_trip() {
+        use SleepStages::*;
+        assert_round_trip(&[Undefined, Awake, Rem, Light, Deep], |s| {
+            matches![s, Invalid(_)]
+        });
+    }
+}
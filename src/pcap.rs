@@ -0,0 +1,186 @@
+// rawzeo::pcap
+//
+//! Export of the byte stream segments to PCAP files.
+//
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{Error, FrameHeader};
+
+/// The link-layer type of the packets, `DLT_USER0`.
+pub const PCAP_LINKTYPE: u32 = 147;
+
+/// The kind of each exported segment, stored in the first byte of its packet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum PcapSegment {
+    /// A valid frame.
+    Frame = 0,
+
+    /// Bytes skipped while resynchronizing with the start of a frame.
+    Skipped = 1,
+
+    /// A frame with an invalid length.
+    InvalidLength = 2,
+
+    /// A frame with an invalid checksum.
+    InvalidChecksum = 3,
+
+    /// A frame with an unsupported protocol version.
+    UnsupportedProtocol = 4,
+}
+
+impl From<Option<Error>> for PcapSegment {
+    fn from(error: Option<Error>) -> PcapSegment {
+        match error {
+            Some(Error::InvalidLength) => PcapSegment::InvalidLength,
+            Some(Error::InvalidChecksum) => PcapSegment::InvalidChecksum,
+            Some(Error::UnsupportedProtocol(_)) => PcapSegment::UnsupportedProtocol,
+            _ => PcapSegment::Skipped,
+        }
+    }
+}
+
+/// Exports the segments of the byte stream as the packets of a PCAP file,
+/// so that they can be analyzed with Wireshark.
+///
+/// Each packet starts with a byte indicating its [`PcapSegment`] kind,
+/// followed by the raw bytes as received, and it's timestamped with the
+/// time when it was received by the host.
+/// The link-layer type is [`PCAP_LINKTYPE`].
+#[derive(Debug)]
+pub struct PcapExporter<W: Write> {
+    out: W,
+}
+
+impl PcapExporter<BufWriter<File>> {
+    /// Creates the PCAP file at `path`.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> PcapExporter<W> {
+    /// Returns a new exporter over `out`, writing the file header.
+    pub fn new(mut out: W) -> io::Result<Self> {
+        out.write_all(&0xA1B2_C3D4_u32.to_le_bytes())?; // magic, microseconds
+        out.write_all(&2_u16.to_le_bytes())?; // major version
+        out.write_all(&4_u16.to_le_bytes())?; // minor version
+        out.write_all(&0_i32.to_le_bytes())?; // time zone offset
+        out.write_all(&0_u32.to_le_bytes())?; // timestamp accuracy
+        out.write_all(&(u16::MAX as u32).to_le_bytes())?; // snapshot length
+        out.write_all(&PCAP_LINKTYPE.to_le_bytes())?;
+        Ok(Self { out })
+    }
+
+    /// Writes a valid frame, received at `time`.
+    pub fn write_frame(
+        &mut self,
+        time: SystemTime,
+        header: &FrameHeader,
+        data: &[u8],
+    ) -> io::Result<()> {
+        self.write_packet(time, PcapSegment::Frame, &[&header.to_bytes(), data])
+    }
+
+    /// Writes some skipped bytes, received at `time`,
+    /// with the error that made them invalid, if any.
+    pub fn write_skipped(
+        &mut self,
+        time: SystemTime,
+        bytes: &[u8],
+        error: Option<Error>,
+    ) -> io::Result<()> {
+        self.write_packet(time, error.into(), &[bytes])
+    }
+
+    /// Flushes the output.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    /// Writes a packet of the given `kind` made of some `parts`.
+    fn write_packet(
+        &mut self,
+        time: SystemTime,
+        kind: PcapSegment,
+        parts: &[&[u8]],
+    ) -> io::Result<()> {
+        let time = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let len = 1 + parts.iter().map(|p| p.len()).sum::<usize>() as u32;
+        self.out.write_all(&(time.as_secs() as u32).to_le_bytes())?;
+        self.out.write_all(&time.subsec_micros().to_le_bytes())?;
+        self.out.write_all(&len.to_le_bytes())?; // captured length
+        self.out.write_all(&len.to_le_bytes())?; // original length
+        self.out.write_all(&[kind as u8])?;
+        for part in parts {
+            self.out.write_all(part)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{frame::encode, DataType, Decoder, Segment};
+    use std::time::Duration;
+
+    #[test]
+    fn framing() {
+        let frame = encode(4, 0, 0, DataType::SleepStage, &[3, 0, 0, 0]);
+        let mut bad = encode(4, 0, 1, DataType::Sqi, &[9, 0, 0, 0]);
+        bad[2] = 0;
+        let mut decoder = Decoder::<1024>::new();
+        decoder.push(b"junk");
+        decoder.push(&frame);
+        decoder.push(&bad);
+        decoder.push(&frame);
+
+        let time = UNIX_EPOCH + Duration::from_micros(1_675_288_800_123_456);
+        let mut pcap = PcapExporter::new(vec![]).unwrap();
+        while let Some(segment) = decoder.decode_segment() {
+            match segment {
+                Segment::Frame(f) => pcap.write_frame(time, &f.header, f.data),
+                Segment::Skipped { bytes, error } => pcap.write_skipped(time, bytes, error),
+            }
+            .unwrap();
+        }
+        let out = pcap.out;
+
+        let header: Vec<u8> = [
+            &0xA1B2_C3D4_u32.to_le_bytes()[..],
+            &[2, 0, 4, 0],
+            &[0; 8],
+            &[0xFF, 0xFF, 0, 0],
+            &[147, 0, 0, 0],
+        ]
+        .concat();
+        assert_eq![out[..24], header];
+
+        let mut packets = vec![];
+        let mut rest = &out[24..];
+        while !rest.is_empty() {
+            let u32_at = |i: usize| u32::from_le_bytes(rest[i..i + 4].try_into().unwrap());
+            assert_eq![(u32_at(0), u32_at(4)), (1_675_288_800, 123_456)];
+            let len = u32_at(8) as usize;
+            assert_eq![u32_at(12) as usize, len];
+            packets.push((rest[16], &rest[17..16 + len]));
+            rest = &rest[16 + len..];
+        }
+        assert_eq![
+            packets,
+            [
+                (PcapSegment::Skipped as u8, &b"junk"[..]),
+                (PcapSegment::Frame as u8, &frame[..]),
+                (PcapSegment::InvalidChecksum as u8, &bad[..]),
+                (PcapSegment::Frame as u8, &frame[..]),
+            ]
+        ];
+    }
+}
@@ -0,0 +1,572 @@
+// rawzeo::sqlite
+//
+//! Storage of decoded data in a SQLite database.
+//
+
+use std::{
+    io::{self, Read, Write},
+    path::Path,
+};
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use rusqlite::{
+    params, types::Type, Connection, Error::FromSqlConversionFailure, OptionalExtension, Row,
+};
+
+use crate::{Data, EventType, SleepStages, FREQUENCY_BINS_LEN, WAVEFORM_LEN};
+
+/// The schema of the database created by [`SqliteStore`].
+///
+/// All the times are absolute unix times in seconds, as sent by the Zeo.
+pub const SQLITE_SCHEMA: &str = "
+-- The recording sessions, usually nights. A session is started by its first
+-- data, or by a night start or headband undocked event, and it's ended by
+-- a night end or headband docked event.
+CREATE TABLE IF NOT EXISTS sessions (
+    id INTEGER PRIMARY KEY,
+    start INTEGER NOT NULL, -- the time of its first data
+    end INTEGER NOT NULL    -- the time of its last data
+);
+
+-- The data of each second, or slice. The missing values are NULL.
+CREATE TABLE IF NOT EXISTS slices (
+    session INTEGER NOT NULL REFERENCES sessions (id),
+    time INTEGER NOT NULL,
+    -- the raw power of each frequency bin
+    delta INTEGER,
+    theta INTEGER,
+    alpha INTEGER,
+    beta_mid INTEGER,
+    beta_high INTEGER,
+    beta_low INTEGER,
+    gamma INTEGER,
+    sqi INTEGER,            -- the signal quality index (0..=30)
+    impedance INTEGER,      -- the raw impedance
+    bad_signal INTEGER,     -- 1 if the signal contains artifacts, else 0
+    waveform BLOB,          -- the 128 raw samples, delta encoded (the first
+                            -- sample followed by the wrapping differences
+                            -- with the previous one), as little-endian i16,
+                            -- compressed with zlib
+    PRIMARY KEY (session, time)
+);
+
+-- The sleep stage of each 30 second epoch.
+CREATE TABLE IF NOT EXISTS stages (
+    session INTEGER NOT NULL REFERENCES sessions (id),
+    time INTEGER NOT NULL,
+    -- 0 undefined, 1 awake, 2 REM, 3 light, 4 deep
+    stage INTEGER NOT NULL,
+    PRIMARY KEY (session, time)
+);
+
+-- The events, by their code, e.g. 5 for night start, 7 for sleep onset,
+-- 14 for headband docked, 15 for headband undocked, 21 for night end.
+CREATE TABLE IF NOT EXISTS events (
+    session INTEGER NOT NULL REFERENCES sessions (id),
+    time INTEGER NOT NULL,
+    event INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS events_by_session ON events (session, time);
+";
+
+/// Stores decoded data in a SQLite database, and loads it back.
+///
+/// The data is grouped into sessions, usually nights, and stored in the tables
+/// described by [`SQLITE_SCHEMA`], so that many nights can be kept and queried
+/// in a single file:
+///
+/// | table      | one row per  | columns                                         |
+/// |------------|--------------|-------------------------------------------------|
+/// | `sessions` | session      | `id`, `start`, `end`                            |
+/// | `slices`   | second       | `session`, `time`, `delta`, … , `gamma`, `sqi`, |
+/// |            |              | `impedance`, `bad_signal`, `waveform`           |
+/// | `stages`   | 30sec epoch  | `session`, `time`, `stage`                      |
+/// | `events`   | event        | `session`, `time`, `event`                      |
+///
+/// The written data is committed on each [`flush`][SqliteStore::flush].
+#[derive(Debug)]
+pub struct SqliteStore {
+    conn: Connection,
+
+    // the current session
+    session: Option<Session>,
+    // whether the current session has any slices or stages
+    session_used: bool,
+    // the slice being received
+    slice: Option<Slice>,
+}
+
+/// A recording session, usually a night.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Session {
+    /// The identifier of the session.
+    pub id: i64,
+    /// The unix time of its first data.
+    pub start: u32,
+    /// The unix time of its last data.
+    pub end: u32,
+}
+
+/// The data received during a second.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Slice {
+    /// The unix time.
+    pub time: u32,
+    /// The frequency bins, indexed by [`FrequencyBins`][crate::FrequencyBins].
+    pub bins: Option<[u16; FREQUENCY_BINS_LEN]>,
+    /// The signal quality index.
+    pub sqi: Option<u32>,
+    /// The raw impedance.
+    pub impedance: Option<u32>,
+    /// Whether the signal contains artifacts.
+    pub bad_signal: Option<bool>,
+    /// The raw samples of the waveform.
+    pub waveform: Option<Vec<i16>>,
+}
+
+/// All the data of a session.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Night {
+    /// The session.
+    pub session: Session,
+    /// The slices, by time.
+    pub slices: Vec<Slice>,
+    /// The sleep stages, with the time when they were received.
+    pub stages: Vec<(u32, SleepStages)>,
+    /// The events, with the time when they were received.
+    pub events: Vec<(u32, EventType)>,
+}
+
+impl SqliteStore {
+    /// Opens the database at `path`, creating it and its tables if needed.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let conn = Connection::open(path).map_err(db_error)?;
+        Self::new(conn)
+    }
+
+    /// Returns a new store over the database `conn`,
+    /// creating its tables if needed.
+    pub fn new(conn: Connection) -> io::Result<Self> {
+        // so that the database can be read while it's written
+        conn.query_row("PRAGMA journal_mode = WAL", [], |_| Ok(()))
+            .map_err(db_error)?;
+        conn.execute_batch("PRAGMA synchronous = NORMAL;")
+            .map_err(db_error)?;
+        conn.execute_batch(SQLITE_SCHEMA).map_err(db_error)?;
+        Ok(Self {
+            conn,
+            session: None,
+            session_used: false,
+            slice: None,
+        })
+    }
+
+    /// Writes the `data` received at the given unix `time` in seconds.
+    pub fn write(&mut self, time: u32, data: &Data) -> io::Result<()> {
+        self.write_data(time, data).map_err(db_error)
+    }
+
+    /// Writes any pending slice, and commits the written data.
+    ///
+    /// The pending slice is kept, so that it can still be completed.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.store_slice().map_err(db_error)?;
+        self.update_session().map_err(db_error)?;
+        if !self.conn.is_autocommit() {
+            self.conn.execute_batch("COMMIT").map_err(db_error)?;
+        }
+        Ok(())
+    }
+
+    /// Returns all the sessions, by time.
+    pub fn sessions(&self) -> io::Result<Vec<Session>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id, start, end FROM sessions ORDER BY start, id")
+            .map_err(db_error)?;
+        let rows = stmt.query_map([], session_from_row).map_err(db_error)?;
+        rows.collect::<rusqlite::Result<_>>().map_err(db_error)
+    }
+
+    /// Returns the last session that started at or before the unix `time`,
+    /// if any.
+    pub fn session_at(&self, time: u32) -> io::Result<Option<Session>> {
+        self.conn
+            .query_row(
+                "SELECT id, start, end FROM sessions WHERE start <= ?1
+                 ORDER BY start DESC, id DESC LIMIT 1",
+                [time],
+                session_from_row,
+            )
+            .optional()
+            .map_err(db_error)
+    }
+
+    /// Loads all the data of the session with the given `id`.
+    pub fn load_night(&self, id: i64) -> io::Result<Night> {
+        self.load(id).map_err(db_error)
+    }
+
+    fn load(&self, id: i64) -> rusqlite::Result<Night> {
+        let session = self.conn.query_row(
+            "SELECT id, start, end FROM sessions WHERE id = ?1",
+            [id],
+            session_from_row,
+        )?;
+
+        let mut stmt = self.conn.prepare(
+            "SELECT time, delta, theta, alpha, beta_mid, beta_high, beta_low, gamma,
+                 sqi, impedance, bad_signal, waveform
+             FROM slices WHERE session = ?1 ORDER BY time",
+        )?;
+        let slices = stmt
+            .query_map([id], |row| {
+                let mut bins = [0; FREQUENCY_BINS_LEN];
+                let mut has_bins = true;
+                for (i, bin) in bins.iter_mut().enumerate() {
+                    match row.get::<_, Option<u16>>(1 + i)? {
+                        Some(value) => *bin = value,
+                        None => has_bins = false,
+                    }
+                }
+                let waveform: Option<Vec<u8>> = row.get(11)?;
+                Ok(Slice {
+                    time: row.get(0)?,
+                    bins: has_bins.then_some(bins),
+                    sqi: row.get(8)?,
+                    impedance: row.get(9)?,
+                    bad_signal: row.get(10)?,
+                    waveform: waveform
+                        .as_deref()
+                        .map(decompress)
+                        .transpose()
+                        .map_err(|e| FromSqlConversionFailure(11, Type::Blob, e.into()))?,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+
+        let mut stmt = self
+            .conn
+            .prepare("SELECT time, stage FROM stages WHERE session = ?1 ORDER BY time")?;
+        let stages = stmt
+            .query_map([id], |row| {
+                Ok((row.get(0)?, SleepStages::from(row.get::<_, u8>(1)?)))
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+
+        let mut stmt = self
+            .conn
+            .prepare("SELECT time, event FROM events WHERE session = ?1 ORDER BY time, rowid")?;
+        let events = stmt
+            .query_map([id], |row| {
+                Ok((row.get(0)?, EventType::from(row.get::<_, u8>(1)?)))
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+
+        Ok(Night {
+            session,
+            slices,
+            stages,
+            events,
+        })
+    }
+
+    fn write_data(&mut self, time: u32, data: &Data) -> rusqlite::Result<()> {
+        use EventType::*;
+
+        if let Some(slice) = &self.slice {
+            if slice.time != time {
+                self.end_slice()?;
+            }
+        }
+        match data {
+            Data::Waveform(samples) => self.slice_at(time).waveform = Some(samples.to_vec()),
+            Data::FrequencyBins(bins) => self.slice_at(time).bins = Some(*bins),
+            Data::Sqi(sqi) => self.slice_at(time).sqi = Some(*sqi),
+            Data::Impedance(imp) => self.slice_at(time).impedance = Some(*imp),
+            Data::BadSignal(bad) => self.slice_at(time).bad_signal = Some(*bad),
+            Data::SleepStage(stage) => {
+                let session = self.session_at_time(time)?;
+                self.conn.execute(
+                    "INSERT OR REPLACE INTO stages (session, time, stage) VALUES (?1, ?2, ?3)",
+                    params![session, time, u8::from(*stage)],
+                )?;
+                self.session_used = true;
+            }
+            Data::Event(event) => {
+                if matches!(event, NightStart | HeadbandUnDocked) && self.session_used {
+                    self.end_slice()?;
+                    self.end_session()?;
+                }
+                let session = self.session_at_time(time)?;
+                self.conn.execute(
+                    "INSERT INTO events (session, time, event) VALUES (?1, ?2, ?3)",
+                    params![session, time, u8::from(*event)],
+                )?;
+                if matches!(event, NightEnd | HeadbandDocked) {
+                    self.end_slice()?;
+                    self.end_session()?;
+                }
+            }
+            Data::SliceEnd(_) => self.end_slice()?,
+            Data::Version(_) | Data::ZeoTimestamp(_) => (),
+        }
+        Ok(())
+    }
+
+    /// Returns the slice at `time`.
+    fn slice_at(&mut self, time: u32) -> &mut Slice {
+        self.slice.get_or_insert(Slice {
+            time,
+            ..Default::default()
+        })
+    }
+
+    /// Writes the current slice, if there's one, and starts a new one.
+    fn end_slice(&mut self) -> rusqlite::Result<()> {
+        self.store_slice()?;
+        self.slice = None;
+        Ok(())
+    }
+
+    /// Writes the current slice, if there's one, replacing any previous
+    /// version of it.
+    fn store_slice(&mut self) -> rusqlite::Result<()> {
+        let slice = match &self.slice {
+            Some(slice) => slice.clone(),
+            None => return Ok(()),
+        };
+        let session = self.session_at_time(slice.time)?;
+        let bin = |i: usize| slice.bins.map(|bins| bins[i]);
+        let waveform = slice.waveform.as_deref().map(compress);
+        self.conn.execute(
+            "INSERT OR REPLACE INTO slices (session, time,
+                 delta, theta, alpha, beta_mid, beta_high, beta_low, gamma,
+                 sqi, impedance, bad_signal, waveform)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+            params![
+                session,
+                slice.time,
+                bin(0),
+                bin(1),
+                bin(2),
+                bin(3),
+                bin(4),
+                bin(5),
+                bin(6),
+                slice.sqi,
+                slice.impedance,
+                slice.bad_signal,
+                waveform,
+            ],
+        )?;
+        self.session_used = true;
+        Ok(())
+    }
+
+    /// Returns the identifier of the current session, extended up to `time`,
+    /// starting a new one if needed, within a transaction.
+    fn session_at_time(&mut self, time: u32) -> rusqlite::Result<i64> {
+        if self.conn.is_autocommit() {
+            self.conn.execute_batch("BEGIN")?;
+        }
+        match &mut self.session {
+            Some(session) => {
+                session.end = session.end.max(time);
+                Ok(session.id)
+            }
+            None => {
+                self.conn
+                    .execute("INSERT INTO sessions (start, end) VALUES (?1, ?1)", [time])?;
+                let id = self.conn.last_insert_rowid();
+                self.session = Some(Session {
+                    id,
+                    start: time,
+                    end: time,
+                });
+                self.session_used = false;
+                Ok(id)
+            }
+        }
+    }
+
+    /// Updates the end of the current session.
+    fn update_session(&mut self) -> rusqlite::Result<()> {
+        if let Some(session) = self.session {
+            self.conn.execute(
+                "UPDATE sessions SET end = ?2 WHERE id = ?1",
+                params![session.id, session.end],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Ends the current session, if there's one.
+    fn end_session(&mut self) -> rusqlite::Result<()> {
+        self.update_session()?;
+        self.session = None;
+        self.session_used = false;
+        Ok(())
+    }
+}
+
+/// Returns a session from a row of its `id`, `start` and `end`.
+fn session_from_row(row: &Row) -> rusqlite::Result<Session> {
+    Ok(Session {
+        id: row.get(0)?,
+        start: row.get(1)?,
+        end: row.get(2)?,
+    })
+}
+
+/// Compresses the waveform `samples`, delta encoded.
+fn compress(samples: &[i16]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::with_capacity(WAVEFORM_LEN), Compression::default());
+    let mut previous = 0_i16;
+    for s in samples {
+        // writing to a vector can't fail
+        let _ = encoder.write_all(&s.wrapping_sub(previous).to_le_bytes());
+        previous = *s;
+    }
+    encoder.finish().unwrap_or_default()
+}
+
+/// Decompresses the delta encoded waveform samples of a `blob`.
+fn decompress(blob: &[u8]) -> io::Result<Vec<i16>> {
+    let mut bytes = Vec::with_capacity(WAVEFORM_LEN * 2);
+    ZlibDecoder::new(blob).read_to_end(&mut bytes)?;
+    let mut previous = 0_i16;
+    Ok(bytes
+        .chunks_exact(2)
+        .map(|b| {
+            previous = previous.wrapping_add(i16::from_le_bytes([b[0], b[1]]));
+            previous
+        })
+        .collect())
+}
+
+/// Converts a database error into an I/O error.
+fn db_error(e: rusqlite::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> SqliteStore {
+        SqliteStore::new(Connection::open_in_memory().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn waveform_compression() {
+        let mut samples = [0_i16; WAVEFORM_LEN];
+        for (i, s) in samples.iter_mut().enumerate() {
+            *s = (i as i16 - 64) * 511;
+        }
+        // the differences between these wrap around
+        samples[0] = i16::MIN;
+        samples[1] = i16::MAX;
+        samples[2] = i16::MIN;
+        let blob = compress(&samples);
+        assert![blob.len() < WAVEFORM_LEN * 2];
+        assert_eq![decompress(&blob).unwrap(), samples.to_vec()];
+        assert![decompress(&[1, 2, 3]).is_err()];
+    }
+
+    #[test]
+    fn write_and_load() {
+        let mut db = store();
+        let waveform = [7; WAVEFORM_LEN];
+        let bins = [1, 2, 3, 4, 5, 6, 7];
+        db.write(1000, &Data::Event(EventType::HeadbandUnDocked))
+            .unwrap();
+        db.write(1000, &Data::Waveform(waveform)).unwrap();
+        db.write(1000, &Data::FrequencyBins(bins)).unwrap();
+        db.write(1000, &Data::Sqi(25)).unwrap();
+        db.write(1000, &Data::SliceEnd(0)).unwrap();
+        db.write(1001, &Data::Impedance(300)).unwrap();
+        // a flush stores the pending slice, which can still be completed
+        db.flush().unwrap();
+        db.write(1001, &Data::BadSignal(true)).unwrap();
+        db.write(1030, &Data::SleepStage(SleepStages::Light))
+            .unwrap();
+        db.flush().unwrap();
+
+        let sessions = db.sessions().unwrap();
+        assert_eq![sessions.len(), 1];
+        let session = sessions[0];
+        assert_eq![(session.start, session.end), (1000, 1030)];
+        assert_eq![db.session_at(1015).unwrap(), Some(session)];
+        assert_eq![db.session_at(999).unwrap(), None];
+
+        let night = db.load_night(session.id).unwrap();
+        assert_eq![night.session, session];
+        assert_eq![
+            night.slices,
+            vec![
+                Slice {
+                    time: 1000,
+                    bins: Some(bins),
+                    sqi: Some(25),
+                    waveform: Some(waveform.to_vec()),
+                    ..Default::default()
+                },
+                Slice {
+                    time: 1001,
+                    impedance: Some(300),
+                    bad_signal: Some(true),
+                    ..Default::default()
+                },
+            ]
+        ];
+        assert_eq![night.stages, vec![(1030, SleepStages::Light)]];
+        assert_eq![night.events, vec![(1000, EventType::HeadbandUnDocked)]];
+    }
+
+    #[test]
+    fn session_splitting() {
+        use EventType::*;
+        let mut db = store();
+        let stage = Data::SleepStage(SleepStages::Rem);
+        // a night start right after the first data doesn't split the session
+        db.write(100, &Data::Sqi(20)).unwrap();
+        db.write(100, &Data::Event(NightStart)).unwrap();
+        db.write(130, &stage).unwrap();
+        db.write(160, &Data::Event(NightEnd)).unwrap();
+        // any data after the end starts a new session
+        db.write(200, &stage).unwrap();
+        // which is split by an undocking
+        db.write(300, &Data::Event(HeadbandUnDocked)).unwrap();
+        db.write(330, &stage).unwrap();
+        db.write(400, &Data::Event(HeadbandDocked)).unwrap();
+        db.write(500, &Data::Event(HeadbandUnDocked)).unwrap();
+        db.write(530, &stage).unwrap();
+        // a night start after some data splits the session
+        db.write(600, &Data::Event(NightStart)).unwrap();
+        db.flush().unwrap();
+
+        let spans: Vec<_> = db
+            .sessions()
+            .unwrap()
+            .iter()
+            .map(|s| (s.start, s.end))
+            .collect();
+        assert_eq![
+            spans,
+            vec![(100, 160), (200, 200), (300, 400), (500, 530), (600, 600)]
+        ];
+        let events = |i: usize| {
+            let id = db.sessions().unwrap()[i].id;
+            db.load_night(id).unwrap().events
+        };
+        assert_eq![events(0), vec![(100, NightStart), (160, NightEnd)]];
+        assert_eq![events(1), vec![]];
+        assert_eq![
+            events(2),
+            vec![(300, HeadbandUnDocked), (400, HeadbandDocked)]
+        ];
+        assert_eq![events(3), vec![(500, HeadbandUnDocked)]];
+        assert_eq![events(4), vec![(600, NightStart)]];
+    }
+}
@@ -0,0 +1,201 @@
+// rawzeo::stream
+//
+//! Asynchronous streams of messages over tokio's [`AsyncRead`].
+//
+
+use std::{
+    io,
+    pin::Pin,
+    task::{Context as TaskContext, Poll},
+};
+
+use futures_core::Stream;
+use log::warn;
+use tokio::io::{AsyncRead, ReadBuf};
+
+use crate::{Decoder, Message};
+
+/// The maximum number of bytes read on each poll.
+const READ_LEN: usize = 512;
+
+/// A stream of the messages decoded from an asynchronous `reader`,
+/// like a serial port, a TCP stream or a file.
+///
+/// The received bytes are buffered in a [`Decoder`] of capacity `CAP`,
+/// which is never asked to hold more bytes than it has room for.
+/// Invalid frames are skipped and counted in its [`Stats`][crate::Stats].
+///
+/// It's cancellation safe: all the state is kept between polls, so that
+/// no bytes are lost when the stream is dropped from a `select!` branch.
+#[derive(Debug)]
+pub struct MessageStream<R, const CAP: usize = 1024> {
+    reader: R,
+    decoder: Decoder<CAP>,
+    eof: bool,
+}
+
+impl<R: AsyncRead + Unpin, const CAP: usize> MessageStream<R, CAP> {
+    /// Returns a new stream of the messages decoded from the `reader`.
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            decoder: Decoder::new(),
+            eof: false,
+        }
+    }
+
+    /// Returns the decoder.
+    pub fn decoder(&self) -> &Decoder<CAP> {
+        &self.decoder
+    }
+
+    /// Returns a reference to the reader.
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// Returns a mutable reference to the reader.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    /// Returns the reader, discarding any buffered bytes.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: AsyncRead + Unpin, const CAP: usize> Stream for MessageStream<R, CAP> {
+    type Item = io::Result<Message>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            loop {
+                match this.decoder.decode() {
+                    Ok(Some(msg)) => return Poll::Ready(Some(Ok(msg))),
+                    Ok(None) => break,
+                    Err(e) => warn!("{e}"),
+                }
+            }
+            if this.eof {
+                return Poll::Ready(None);
+            }
+
+            let mut buf = [0; READ_LEN];
+            let len = READ_LEN.min(this.decoder.available());
+            let mut buf = ReadBuf::new(&mut buf[..len]);
+            match Pin::new(&mut this.reader).poll_read(cx, &mut buf) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Err(e)) => return Poll::Ready(Some(Err(e))),
+                Poll::Ready(Ok(())) if buf.filled().is_empty() => this.eof = true,
+                Poll::Ready(Ok(())) => {
+                    this.decoder.push(buf.filled());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{frame::encode, Data, DataType, SleepStages};
+    use std::{
+        collections::VecDeque,
+        sync::Arc,
+        task::{Wake, Waker},
+    };
+
+    /// A reader returning each chunk after a pending poll,
+    /// and then an error if any.
+    struct ChunkReader {
+        chunks: VecDeque<Vec<u8>>,
+        pending: bool,
+        error: Option<io::ErrorKind>,
+    }
+
+    impl AsyncRead for ChunkReader {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut TaskContext<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            self.pending = !self.pending;
+            if self.pending {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            match self.chunks.pop_front() {
+                Some(mut chunk) => {
+                    let len = chunk.len().min(buf.remaining());
+                    buf.put_slice(&chunk[..len]);
+                    if len < chunk.len() {
+                        self.chunks.push_front(chunk.split_off(len));
+                    }
+                }
+                None => {
+                    if let Some(kind) = self.error.take() {
+                        return Poll::Ready(Err(kind.into()));
+                    }
+                }
+            }
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    struct NoopWaker;
+
+    impl Wake for NoopWaker {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    /// Polls the `stream` until it's ready, counting the pending polls.
+    fn next<S: Stream + Unpin>(stream: &mut S, pending: &mut usize) -> Option<S::Item> {
+        let waker = Waker::from(Arc::new(NoopWaker));
+        let mut cx = TaskContext::from_waker(&waker);
+        loop {
+            match Pin::new(&mut *stream).poll_next(&mut cx) {
+                Poll::Ready(item) => return item,
+                Poll::Pending => *pending += 1,
+            }
+        }
+    }
+
+    #[test]
+    fn messages() {
+        let waveform = encode(4, 0, 0, DataType::Waveform, &[1; 256]);
+        let stage = |seqnum| encode(4, 0, seqnum, DataType::SleepStage, &[4, 0, 0, 0]);
+        let bytes = [&waveform[..], b"junk", &stage(1), &stage(2)].concat();
+        let reader = ChunkReader {
+            // the frames are split across the reads
+            chunks: [&bytes[..100], &bytes[100..300], &bytes[300..]]
+                .map(<[u8]>::to_vec)
+                .into(),
+            pending: false,
+            error: Some(io::ErrorKind::ConnectionReset),
+        };
+        // with room for little more than a waveform
+        let mut stream = MessageStream::<_, 300>::new(reader);
+        let mut pending = 0;
+
+        let msg = next(&mut stream, &mut pending).unwrap().unwrap();
+        assert_eq![msg.decode(), Ok(Data::Waveform([257; 128]))];
+        for seqnum in [1, 2] {
+            let msg = next(&mut stream, &mut pending).unwrap().unwrap();
+            assert_eq![msg.seqnum, seqnum];
+            assert_eq![msg.decode(), Ok(Data::SleepStage(SleepStages::Deep))];
+        }
+        let error = next(&mut stream, &mut pending).unwrap().unwrap_err();
+        assert_eq![error.kind(), io::ErrorKind::ConnectionReset];
+        assert![next(&mut stream, &mut pending).is_none()];
+        assert![next(&mut stream, &mut pending).is_none()];
+
+        // the decoder was never asked to hold more bytes than it had room for
+        let stats = stream.decoder().stats();
+        assert_eq![stats.overrun_bytes, 0];
+        assert_eq![stats.skipped_bytes, 4];
+        assert_eq![stats.frames, 3];
+        assert![pending >= 3];
+    }
+}